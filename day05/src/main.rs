@@ -1,15 +1,25 @@
-use std::{cmp::Ordering, time::Instant};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    env, fmt, process,
+    time::Instant,
+};
 
-fn load() -> (Vec<(usize, usize)>, Vec<Vec<usize>>) {
-    let input = include_str!("input.txt");
+fn load() -> (RuleGraph, Vec<Vec<usize>>) {
+    let (rules, updates) = parse(include_str!("input.txt"));
 
+    (RuleGraph::new(&rules), updates)
+}
+
+type Rules = Vec<(usize, usize)>;
+
+fn parse(input: &str) -> (Rules, Vec<Vec<usize>>) {
     let empty_line_index = input
         .lines()
         .map(str::trim)
         .position(|line| line.is_empty())
         .expect("failed to locate empty line");
 
-    let rules = input
+    let rules: Vec<(usize, usize)> = input
         .lines()
         .map(str::trim)
         .take(empty_line_index)
@@ -38,21 +48,324 @@ fn load() -> (Vec<(usize, usize)>, Vec<Vec<usize>>) {
     (rules, updates)
 }
 
-fn check_rule(rule: (usize, usize), update: &Vec<usize>) -> bool {
-    let (first, second) = rule;
+/// Adjacency representation of the page ordering rules, where every `X|Y` rule
+/// is stored as an edge from `X` to `Y`.
+#[derive(Debug, Default)]
+struct RuleGraph {
+    successors: HashMap<usize, HashSet<usize>>,
+}
+
+/// A set of rules that contradict each other, the pages are listed in the
+/// order in which the rules chain them together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RuleCycle(Vec<usize>);
+
+impl fmt::Display for RuleCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule cycle detected: ")?;
+
+        for page in &self.0 {
+            write!(f, "{page} -> ")?;
+        }
+
+        write!(f, "{}", self.0[0])
+    }
+}
 
-    let Some(first_pos) = update.iter().position(|&num| num == first) else {
-        return true;
-    };
+/// A rule `first|second` that is broken by an update, because `first` is printed after `second`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Violation {
+    pub first: usize,
+    pub second: usize,
+    pub first_pos: usize,
+    pub second_pos: usize,
+}
 
-    let Some(second_pos) = update.iter().position(|&num| num == second) else {
-        return true;
-    };
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}|{} ({} at position {}, {} at position {})",
+            self.first, self.second, self.first, self.first_pos, self.second, self.second_pos
+        )
+    }
+}
 
-    first_pos < second_pos
+/// Describes how much freedom the rules leave when ordering the pages of an update.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Ambiguity {
+    /// Pairs of pages that are not ordered by any chain of rules, so either one may come first.
+    pub interchangeable: Vec<(usize, usize)>,
+    /// Every page that can end up in the middle of a valid ordering.
+    pub middle_candidates: Vec<usize>,
+}
+
+impl Ambiguity {
+    pub fn is_unique(&self) -> bool {
+        self.interchangeable.is_empty()
+    }
+
+    pub fn is_middle_ambiguous(&self) -> bool {
+        self.middle_candidates.len() > 1
+    }
+}
+
+impl RuleGraph {
+    pub fn new(rules: &[(usize, usize)]) -> Self {
+        let mut graph = Self::default();
+
+        for &(first, second) in rules {
+            graph.successors.entry(first).or_default().insert(second);
+        }
+
+        graph
+    }
+
+    /// Returns `true` if there is a rule stating that `first` has to be printed before `second`.
+    pub fn must_precede(&self, first: usize, second: usize) -> bool {
+        self.successors
+            .get(&first)
+            .is_some_and(|successors| successors.contains(&second))
+    }
+
+    /// Checks that no rule is violated by the given update, this is linear in the
+    /// length of the update plus the number of rules that start at one of its pages.
+    pub fn is_ordered(&self, update: &[usize]) -> bool {
+        let positions: HashMap<usize, usize> = update
+            .iter()
+            .enumerate()
+            .map(|(index, &page)| (page, index))
+            .collect();
+
+        update.iter().enumerate().all(|(index, page)| {
+            self.successors.get(page).is_none_or(|successors| {
+                successors
+                    .iter()
+                    .filter_map(|successor| positions.get(successor))
+                    .all(|&successor_index| index < successor_index)
+            })
+        })
+    }
+
+    /// Lists every rule that is violated by the given update, ordered by the
+    /// position of the page that is printed too late.
+    pub fn violations(&self, update: &[usize]) -> Vec<Violation> {
+        let positions: HashMap<usize, usize> = update
+            .iter()
+            .enumerate()
+            .map(|(index, &page)| (page, index))
+            .collect();
+
+        let mut violations: Vec<Violation> = update
+            .iter()
+            .enumerate()
+            .flat_map(|(first_pos, &first)| {
+                self.successors
+                    .get(&first)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|second| positions.get_key_value(second))
+                    .filter(move |&(_, &second_pos)| second_pos < first_pos)
+                    .map(move |(&second, &second_pos)| Violation {
+                        first,
+                        second,
+                        first_pos,
+                        second_pos,
+                    })
+            })
+            .collect();
+
+        violations.sort_unstable_by_key(|violation| (violation.first_pos, violation.second_pos));
+        violations
+    }
+
+    /// Orders the pages of an update using a topological sort over the rules
+    /// that only involve pages of this update.
+    pub fn repair(&self, update: &[usize]) -> Result<Vec<usize>, RuleCycle> {
+        let pages: HashSet<usize> = update.iter().copied().collect();
+
+        let mut in_degree: HashMap<usize, usize> = update.iter().map(|&page| (page, 0)).collect();
+        for page in update {
+            for successor in self.local_successors(*page, &pages) {
+                *in_degree.get_mut(&successor).unwrap() += 1;
+            }
+        }
+
+        // seed the queue in update order, so the result is deterministic
+        let mut queue: VecDeque<usize> = update
+            .iter()
+            .copied()
+            .filter(|page| in_degree[page] == 0)
+            .collect();
+
+        let mut order = Vec::with_capacity(update.len());
+
+        while let Some(page) = queue.pop_front() {
+            order.push(page);
+
+            for successor in self.local_successors(page, &pages) {
+                let degree = in_degree.get_mut(&successor).unwrap();
+                *degree -= 1;
+
+                if *degree == 0 {
+                    queue.push_back(successor);
+                }
+            }
+        }
+
+        if order.len() == update.len() {
+            return Ok(order);
+        }
+
+        let remaining: HashSet<usize> = in_degree
+            .into_iter()
+            .filter(|&(_, degree)| degree > 0)
+            .map(|(page, _)| page)
+            .collect();
+
+        Err(self.find_cycle(&remaining))
+    }
+
+    /// Analyses a topologically sorted update, as produced by [`RuleGraph::repair`],
+    /// for pages that could be swapped without violating any rule.
+    pub fn ambiguity(&self, order: &[usize]) -> Ambiguity {
+        let pages: HashSet<usize> = order.iter().copied().collect();
+        let positions: HashMap<usize, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(index, &page)| (page, index))
+            .collect();
+
+        // `reachable[i][j]` is `true` if a chain of rules forces `order[i]` before `order[j]`,
+        // walking backwards means all successors are already resolved.
+        let mut reachable = vec![vec![false; order.len()]; order.len()];
+        for (index, &page) in order.iter().enumerate().rev() {
+            for successor in self.local_successors(page, &pages) {
+                let successor_index = positions[&successor];
+                reachable[index][successor_index] = true;
+
+                let transitive = reachable[successor_index].clone();
+                for (reach, transitive_reach) in reachable[index].iter_mut().zip(transitive) {
+                    *reach |= transitive_reach;
+                }
+            }
+        }
+
+        let mut interchangeable = Vec::new();
+        for first in 0..order.len() {
+            for second in first + 1..order.len() {
+                if !reachable[first][second] {
+                    interchangeable.push((order[first], order[second]));
+                }
+            }
+        }
+
+        // a page can be placed in the middle as long as neither the pages that have to come
+        // before it nor the pages that have to come after it overflow their half
+        let middle = order.len() / 2;
+        let middle_candidates = (0..order.len())
+            .filter(|&index| {
+                let before = (0..order.len())
+                    .filter(|&other| reachable[other][index])
+                    .count();
+                let after = reachable[index].iter().filter(|&&reach| reach).count();
+
+                before <= middle && after < order.len() - middle
+            })
+            .map(|index| order[index])
+            .collect();
+
+        Ambiguity {
+            interchangeable,
+            middle_candidates,
+        }
+    }
+
+    /// Returns the successors of `page` that are part of `pages`, sorted so the
+    /// iteration order does not depend on the hasher.
+    fn local_successors(&self, page: usize, pages: &HashSet<usize>) -> Vec<usize> {
+        let mut successors: Vec<usize> = self
+            .successors
+            .get(&page)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|successor| pages.contains(successor))
+            .collect();
+
+        successors.sort_unstable();
+        successors
+    }
+
+    /// Extracts a cycle from the pages left over by the topological sort. Every
+    /// left over page has a predecessor that is left over as well, so walking
+    /// backwards is guaranteed to revisit a page eventually.
+    fn find_cycle(&self, remaining: &HashSet<usize>) -> RuleCycle {
+        let predecessor_of = |page: usize| {
+            let mut candidates: Vec<usize> = remaining
+                .iter()
+                .copied()
+                .filter(|&candidate| self.must_precede(candidate, page))
+                .collect();
+
+            candidates.sort_unstable();
+            candidates[0]
+        };
+
+        let mut page = *remaining.iter().min().expect("cycle must not be empty");
+        let mut path = Vec::new();
+
+        while !path.contains(&page) {
+            path.push(page);
+            page = predecessor_of(page);
+        }
+
+        let start = path.iter().position(|&p| p == page).unwrap();
+        let mut cycle = path.split_off(start);
+        cycle.reverse();
+
+        RuleCycle(cycle)
+    }
+}
+
+fn format_update(update: &[usize]) -> String {
+    update
+        .iter()
+        .map(usize::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn explain(rules: &RuleGraph, invalid_updates: &[Vec<usize>], repaired_updates: &[Vec<usize>]) {
+    println!("  Explain:");
+
+    for (update, repaired) in invalid_updates.iter().zip(repaired_updates) {
+        println!("   Update: {}", format_update(update));
+
+        for violation in rules.violations(update) {
+            println!("           violates {violation}");
+        }
+
+        println!(" Repaired: {}", format_update(repaired));
+
+        let ambiguity = rules.ambiguity(repaired);
+        if !ambiguity.is_unique() {
+            let pairs = ambiguity
+                .interchangeable
+                .iter()
+                .map(|(first, second)| format!("{first}/{second}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            println!("           interchangeable {pairs}");
+        }
+        println!();
+    }
 }
 
 fn main() {
+    let explain_mode = env::args().skip(1).any(|arg| arg == "--explain");
+
     let load_start = Instant::now();
 
     let (rules, updates) = load();
@@ -65,9 +378,9 @@ fn main() {
 
     let partition_start = Instant::now();
 
-    let (valid_updates, mut invalid_updates): (Vec<_>, Vec<_>) = updates
+    let (valid_updates, invalid_updates): (Vec<_>, Vec<_>) = updates
         .into_iter()
-        .partition(|update| rules.iter().all(|&rule| check_rule(rule, update)));
+        .partition(|update| rules.is_ordered(update));
 
     let partition_elapsed = partition_start.elapsed();
 
@@ -90,23 +403,16 @@ fn main() {
 
     let part_2_start = Instant::now();
 
-    for update in &mut invalid_updates {
-        update.sort_by(|&a, &b| {
-            rules
-                .iter()
-                .find_map(|&(f, s)| match (a == f, b == s) {
-                    (true, true) => Some(Ordering::Less), // if `a == f` and `b == s`, then `a` must come before `b` (`Ordering::Less`)
-                    (false, false) => match (a == s, b == f) {
-                        (true, true) => Some(Ordering::Greater), // if `a == s` and `b == f`, then `b` must come before `a` (`Ordering::Greater`)
-                        _ => None,                               // continue checking other rules
-                    },
-                    _ => None, // continue checking other rules
-                })
-                .unwrap_or(Ordering::Equal) // default to `Ordering::Equal` if no violation.
+    let repaired_updates = invalid_updates
+        .iter()
+        .map(|update| rules.repair(update))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|cycle| {
+            eprintln!("error: {cycle}");
+            process::exit(1);
         });
-    }
 
-    let part_2_solution: usize = invalid_updates
+    let part_2_solution: usize = repaired_updates
         .iter()
         .map(|update| update[update.len() / 2])
         .sum();
@@ -117,9 +423,151 @@ fn main() {
     println!("     Time: {:?}", part_2_elapsed);
     println!();
 
+    // the sum is only meaningful if the rules pin down the middle page of every update
+    for repaired in &repaired_updates {
+        let ambiguity = rules.ambiguity(repaired);
+
+        if ambiguity.is_middle_ambiguous() {
+            println!(
+                "  Warning: middle page of {} is ambiguous, candidates are {}",
+                format_update(repaired),
+                format_update(&ambiguity.middle_candidates)
+            );
+        }
+    }
+
     println!("    Total:");
     println!(
         "     Time: {:?}",
         (load_elapsed) + (part_1_elapsed) + (part_2_elapsed)
     );
+
+    if explain_mode {
+        println!();
+        explain(&rules, &invalid_updates, &repaired_updates);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The per rule check the graph replaced.
+    fn check_rule(rule: (usize, usize), update: &[usize]) -> bool {
+        let (first, second) = rule;
+
+        let Some(first_pos) = update.iter().position(|&num| num == first) else {
+            return true;
+        };
+
+        let Some(second_pos) = update.iter().position(|&num| num == second) else {
+            return true;
+        };
+
+        first_pos < second_pos
+    }
+
+    fn is_rotation(cycle: &[usize], expected: &[usize]) -> bool {
+        cycle.len() == expected.len()
+            && (0..expected.len()).any(|shift| {
+                cycle
+                    .iter()
+                    .zip(expected.iter().cycle().skip(shift))
+                    .all(|(page, expected_page)| page == expected_page)
+            })
+    }
+
+    #[test]
+    fn three_cycle() {
+        let rules = RuleGraph::new(&[(1, 2), (2, 3), (3, 1)]);
+
+        let RuleCycle(cycle) = rules.repair(&[1, 2, 3]).unwrap_err();
+        assert!(is_rotation(&cycle, &[1, 2, 3]), "{cycle:?}");
+    }
+
+    #[test]
+    fn cycle_with_unrelated_pages() {
+        let rules = RuleGraph::new(&[(4, 1), (1, 2), (2, 3), (3, 1)]);
+
+        let RuleCycle(cycle) = rules.repair(&[5, 4, 3, 2, 1]).unwrap_err();
+        assert!(is_rotation(&cycle, &[1, 2, 3]), "{cycle:?}");
+    }
+
+    #[test]
+    fn example_repairs() {
+        let (rules, updates) = parse(include_str!("example.txt"));
+        let graph = RuleGraph::new(&rules);
+
+        let repaired: Vec<Vec<usize>> = updates
+            .iter()
+            .filter(|update| !graph.is_ordered(update))
+            .map(|update| graph.repair(update).unwrap())
+            .collect();
+
+        assert_eq!(
+            repaired,
+            [
+                vec![97, 75, 47, 61, 53],
+                vec![61, 29, 13],
+                vec![97, 75, 47, 29, 13]
+            ]
+        );
+    }
+
+    #[test]
+    fn is_ordered_matches_check_rule() {
+        for input in [include_str!("example.txt"), include_str!("input.txt")] {
+            let (rules, updates) = parse(input);
+            let graph = RuleGraph::new(&rules);
+
+            for update in &updates {
+                assert_eq!(
+                    graph.is_ordered(update),
+                    rules.iter().all(|&rule| check_rule(rule, update)),
+                    "{update:?}"
+                );
+            }
+        }
+    }
+
+    fn ambiguity(rules: &[(usize, usize)], update: &[usize]) -> Ambiguity {
+        let graph = RuleGraph::new(rules);
+        let order = graph.repair(update).unwrap();
+
+        graph.ambiguity(&order)
+    }
+
+    #[test]
+    fn constrained_chain_is_unique() {
+        let ambiguity = ambiguity(&[(1, 2), (2, 3), (3, 4), (4, 5)], &[5, 4, 3, 2, 1]);
+
+        assert!(ambiguity.is_unique());
+        assert_eq!(ambiguity.middle_candidates, [3]);
+    }
+
+    #[test]
+    fn free_pair_is_interchangeable() {
+        let ambiguity = ambiguity(&[(1, 3), (2, 3)], &[3, 2, 1]);
+
+        assert_eq!(ambiguity.interchangeable, [(2, 1)]);
+        assert!(ambiguity.is_middle_ambiguous());
+    }
+
+    #[test]
+    fn single_rule_leaves_every_page_in_the_middle() {
+        let mut candidates = ambiguity(&[(1, 2)], &[2, 1, 3, 4, 5]).middle_candidates;
+        candidates.sort_unstable();
+
+        assert_eq!(candidates, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn pinned_middle_is_not_ambiguous() {
+        // 1 and 2 may swap, as may 4 and 5, but 3 always ends up in the middle
+        let ambiguity = ambiguity(&[(1, 3), (2, 3), (3, 4), (3, 5)], &[5, 4, 3, 2, 1]);
+
+        assert!(!ambiguity.is_unique());
+        assert!(!ambiguity.is_middle_ambiguous());
+        assert_eq!(ambiguity.middle_candidates, [3]);
+    }
 }
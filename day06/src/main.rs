@@ -1,9 +1,10 @@
 use std::{
     collections::HashSet,
-    iter::{once, repeat},
-    mem,
-    sync::atomic::{AtomicUsize, Ordering},
-    thread,
+    env, fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    iter::repeat_n,
+    process,
     time::Instant,
 };
 
@@ -65,10 +66,47 @@ impl Direction {
         }
     }
 
+    pub fn rotate_left(self) -> Self {
+        self.rotate().reverse()
+    }
+
+    pub fn reverse(self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+
     #[inline]
     pub fn mask(self) -> u8 {
         self as u8
     }
+
+    #[inline]
+    pub fn index(self) -> usize {
+        self.mask().trailing_zeros() as usize
+    }
+
+    pub fn from_glyph(ch: char) -> Option<Self> {
+        match ch {
+            '^' => Some(Direction::Up),
+            'v' => Some(Direction::Down),
+            '<' => Some(Direction::Left),
+            '>' => Some(Direction::Right),
+            _ => None,
+        }
+    }
+
+    pub fn glyph(self) -> char {
+        match self {
+            Direction::Up => '^',
+            Direction::Down => 'v',
+            Direction::Left => '<',
+            Direction::Right => '>',
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -87,13 +125,6 @@ impl Map {
         }
     }
 
-    pub fn reset(&mut self) {
-        self.tiles
-            .iter_mut()
-            .filter(|tile| matches!(tile, Tile::Visited(_)))
-            .for_each(|tile| *tile = Tile::Empty);
-    }
-
     pub fn mark_as_visited(&mut self, pos: Position, dir: Direction) {
         let Position { x, y } = pos;
 
@@ -127,54 +158,136 @@ impl Map {
         self.tiles.get(index).copied().unwrap_or(Tile::Outside)
     }
 
-    pub fn set_tile(&mut self, pos: Position, tile: Tile) {
-        let Position { x, y } = pos;
+    /// Marks every tile that was visited on `other` as visited on this map as well.
+    pub fn merge_visited(&mut self, other: &Map) {
+        for (tile, other_tile) in self.tiles.iter_mut().zip(&other.tiles) {
+            match (tile, other_tile) {
+                (Tile::Visited(dirs), Tile::Visited(other_dirs)) => *dirs |= other_dirs,
+                (tile @ Tile::Empty, Tile::Visited(other_dirs)) => {
+                    *tile = Tile::Visited(*other_dirs)
+                }
+                _ => (),
+            }
+        }
+    }
 
-        assert!(x < self.width, "x is out of bounds");
-        assert!(y < self.height, "y is out of bounds");
+    pub fn count_visited(&self) -> usize {
+        self.tiles
+            .iter()
+            .filter(|tile| matches!(tile, Tile::Visited(_)))
+            .count()
+    }
+}
 
-        let index = y * self.width + x;
+/// What the guard does when the tile in front of it is blocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reaction {
+    Turn(Direction),
+    PassThrough,
+}
 
-        let Some(tile_ref) = self.tiles.get_mut(index) else {
-            return;
-        };
+/// The number of distinct states a [`MovementRule`] may report.
+const MAX_RULE_STATES: u8 = 8;
+
+/// Decides how the guard reacts to obstacles, rules may keep state between obstacles.
+trait MovementRule {
+    fn on_obstacle(&mut self, dir: Direction) -> Reaction;
 
-        *tile_ref = tile;
+    /// Identifies the internal state of the rule, the guard is only stuck in a loop if it
+    /// faces the same obstacle in the same direction with the same state. Has to be below
+    /// [`MAX_RULE_STATES`] and `0` is the initial state.
+    fn state(&self) -> u8 {
+        0
     }
 
-    pub fn replace_tile(&mut self, pos: Position, tile: Tile) -> Tile {
-        let Position { x, y } = pos;
+    fn set_state(&mut self, _state: u8) {}
+}
 
-        assert!(x < self.width, "x is out of bounds");
-        assert!(y < self.height, "y is out of bounds");
+/// The rule from the puzzle, the guard always turns right.
+struct TurnRight;
 
-        let index = y * self.width + x;
+impl MovementRule for TurnRight {
+    fn on_obstacle(&mut self, dir: Direction) -> Reaction {
+        Reaction::Turn(dir.rotate())
+    }
+}
+
+struct TurnLeft;
 
-        let Some(tile_ref) = self.tiles.get_mut(index) else {
-            return Tile::Outside;
+impl MovementRule for TurnLeft {
+    fn on_obstacle(&mut self, dir: Direction) -> Reaction {
+        Reaction::Turn(dir.rotate_left())
+    }
+}
+
+struct TurnAround;
+
+impl MovementRule for TurnAround {
+    fn on_obstacle(&mut self, dir: Direction) -> Reaction {
+        Reaction::Turn(dir.reverse())
+    }
+}
+
+/// The guard turns left at the first obstacle, right at the second and so on.
+#[derive(Default)]
+struct AlternateTurns {
+    turn_right: bool,
+}
+
+impl MovementRule for AlternateTurns {
+    fn on_obstacle(&mut self, dir: Direction) -> Reaction {
+        let reaction = if self.turn_right {
+            Reaction::Turn(dir.rotate())
+        } else {
+            Reaction::Turn(dir.rotate_left())
         };
 
-        mem::replace(tile_ref, tile)
+        self.turn_right = !self.turn_right;
+        reaction
     }
 
-    pub fn count_visited(&self) -> usize {
-        self.tiles
-            .iter()
-            .filter(|tile| matches!(tile, Tile::Visited(_)))
-            .count()
+    fn state(&self) -> u8 {
+        self.turn_right as u8
     }
 
-    pub fn visited_iter<'a>(&'a self) -> impl Iterator<Item = Position> + 'a {
-        self.tiles
-            .iter()
-            .enumerate()
-            .filter(|(_, tile)| matches!(tile, Tile::Visited(_)))
-            .map(|(idx, _)| {
-                let y = idx / self.width;
-                let x = idx % self.width;
+    fn set_state(&mut self, state: u8) {
+        self.turn_right = state != 0;
+    }
+}
 
-                Position { x, y }
-            })
+/// The guard walks through the first obstacle it meets and turns right afterwards.
+#[derive(Default)]
+struct PassThroughOnce {
+    passed: bool,
+}
+
+impl MovementRule for PassThroughOnce {
+    fn on_obstacle(&mut self, dir: Direction) -> Reaction {
+        if self.passed {
+            Reaction::Turn(dir.rotate())
+        } else {
+            self.passed = true;
+            Reaction::PassThrough
+        }
+    }
+
+    fn state(&self) -> u8 {
+        self.passed as u8
+    }
+
+    fn set_state(&mut self, state: u8) {
+        self.passed = state != 0;
+    }
+}
+
+fn movement_rule(name: &str) -> Option<Box<dyn MovementRule>> {
+    match name {
+        "right" => Some(Box::new(TurnRight)),
+        "left" => Some(Box::new(TurnLeft)),
+        "around" => Some(Box::new(TurnAround)),
+        "alternate" => Some(Box::new(AlternateTurns::default())),
+        "pass-through" => Some(Box::new(PassThroughOnce::default())),
+        _ => None,
     }
 }
 
@@ -182,6 +295,8 @@ struct Guard<'a> {
     map: &'a mut Map,
     pos: Position,
     dir: Direction,
+    rule: &'a mut dyn MovementRule,
+    turns: HashSet<(Position, Direction, u8)>,
 }
 
 enum StepResult {
@@ -191,8 +306,22 @@ enum StepResult {
 }
 
 impl<'a> Guard<'a> {
-    pub fn new(map: &'a mut Map, pos: Position, dir: Direction) -> Self {
-        Self { map, pos, dir }
+    /// Creates a guard that follows the given rule, starting from the rules initial state.
+    pub fn new(
+        map: &'a mut Map,
+        pos: Position,
+        dir: Direction,
+        rule: &'a mut dyn MovementRule,
+    ) -> Self {
+        rule.set_state(0);
+
+        Self {
+            map,
+            pos,
+            dir,
+            rule,
+            turns: HashSet::new(),
+        }
     }
 
     pub fn step(&mut self) -> StepResult {
@@ -202,22 +331,22 @@ impl<'a> Guard<'a> {
 
         match self.map.get_tile(new_pos) {
             Tile::Obstacle => {
-                self.dir = self.dir.rotate();
+                // we have faced this obstacle before in the same direction and state, that means we found a loop
+                if !self.turns.insert((self.pos, self.dir, self.rule.state())) {
+                    return StepResult::Loop;
+                }
+
+                match self.rule.on_obstacle(self.dir) {
+                    Reaction::Turn(dir) => self.dir = dir,
+                    Reaction::PassThrough => self.pos = new_pos,
+                }
+
                 StepResult::Step
             }
-            Tile::Empty => {
+            Tile::Empty | Tile::Visited(_) => {
                 self.pos = new_pos;
                 StepResult::Step
             }
-            Tile::Visited(dir) => {
-                if (dir & self.dir as u8) != 0 {
-                    // we have been here before looking in the same direction, that means we found loop
-                    StepResult::Loop
-                } else {
-                    self.pos = new_pos;
-                    StepResult::Step
-                }
-            }
             Tile::Outside => {
                 self.pos = new_pos;
                 StepResult::Finish
@@ -236,65 +365,564 @@ impl<'a> Guard<'a> {
     }
 }
 
-fn load() -> (Map, Position) {
-    let input = include_str!("input.txt");
+/// For every tile and direction this stores the index of the tile at which the guard
+/// has to turn next, `None` means the guard walks off the map instead. Indices are
+/// kept as `u32` so the whole table stays small enough to be cache friendly.
+#[derive(Debug, Clone)]
+struct JumpTable {
+    jumps: Vec<[Option<u32>; 4]>,
+    width: usize,
+    height: usize,
+    patches: Vec<(usize, Direction, Option<u32>)>,
+}
+
+impl JumpTable {
+    pub fn new(map: &Map) -> Self {
+        let mut table = Self {
+            jumps: vec![[None; 4]; map.width * map.height],
+            width: map.width,
+            height: map.height,
+            patches: Vec::new(),
+        };
+
+        for dir in [
+            Direction::Up,
+            Direction::Down,
+            Direction::Left,
+            Direction::Right,
+        ] {
+            // start at the edge the guard is walking towards and sweep backwards,
+            // remembering the tile in front of the most recent obstacle
+            let edge: Vec<Position> = match dir {
+                Direction::Up => (0..map.width).map(|x| Position { x, y: 0 }).collect(),
+                Direction::Down => (0..map.width)
+                    .map(|x| Position {
+                        x,
+                        y: map.height - 1,
+                    })
+                    .collect(),
+                Direction::Left => (0..map.height).map(|y| Position { x: 0, y }).collect(),
+                Direction::Right => (0..map.height)
+                    .map(|y| Position {
+                        x: map.width - 1,
+                        y,
+                    })
+                    .collect(),
+            };
+
+            for start in edge {
+                let mut stop = None;
+                let mut pos = Some(start);
+
+                while let Some(current) = pos {
+                    let index = table.index(current);
+                    table.jumps[index][dir.index()] = stop;
+
+                    if map.get_tile(current) == Tile::Obstacle {
+                        stop = table
+                            .neighbour(current, dir.reverse())
+                            .map(|pos| table.index(pos) as u32);
+                    }
+
+                    pos = table.neighbour(current, dir.reverse());
+                }
+            }
+        }
+
+        table
+    }
+
+    fn index(&self, pos: Position) -> usize {
+        pos.y * self.width + pos.x
+    }
+
+    fn position(&self, index: usize) -> Position {
+        Position {
+            x: index % self.width,
+            y: index / self.width,
+        }
+    }
+
+    fn neighbour(&self, pos: Position, dir: Direction) -> Option<Position> {
+        let next = pos.step(dir);
+
+        (next != pos && next.x < self.width && next.y < self.height).then_some(next)
+    }
+
+    /// Places a temporary obstacle, only the jumps along its row and column that
+    /// now end in front of it are patched.
+    pub fn place_obstacle(&mut self, map: &Map, obstacle: Position) {
+        for dir in [
+            Direction::Up,
+            Direction::Down,
+            Direction::Left,
+            Direction::Right,
+        ] {
+            let mut pos = self.neighbour(obstacle, dir.reverse());
+            let stop = pos.map(|pos| self.index(pos) as u32);
+
+            while let Some(current) = pos {
+                let index = self.index(current);
+                let previous = self.jumps[index][dir.index()];
+
+                self.patches.push((index, dir, previous));
+                self.jumps[index][dir.index()] = stop;
+
+                // everything behind an existing obstacle already stops in front of it
+                if map.get_tile(current) == Tile::Obstacle {
+                    break;
+                }
+
+                pos = self.neighbour(current, dir.reverse());
+            }
+        }
+    }
+
+    /// Reverts all patches made by [`JumpTable::place_obstacle`].
+    pub fn remove_obstacles(&mut self) {
+        while let Some((index, dir, previous)) = self.patches.pop() {
+            self.jumps[index][dir.index()] = previous;
+        }
+    }
+
+    /// Lets the guard jump from obstacle to obstacle, a loop is detected as soon as
+    /// the guard faces the same obstacle in the same direction and rule state twice.
+    pub fn simulate(
+        &self,
+        start: (Position, Direction, u8),
+        rule: &mut dyn MovementRule,
+        turns: &mut TurnLog,
+    ) -> Option<Cycle> {
+        turns.clear();
+
+        let (start_pos, start_dir, start_state) = start;
+        rule.set_state(start_state);
 
-    let height = 2 + input.lines().filter(|line| !line.is_empty()).count();
+        let mut pos = self.index(start_pos);
+        let mut dir = start_dir;
+
+        loop {
+            let Some(stop) = self.jumps[pos][dir.index()] else {
+                break None;
+            };
+
+            let stop = stop as usize;
+            let state = rule.state();
+
+            if !turns.insert(stop, dir, state) {
+                let turn_points: Vec<Position> = turns
+                    .since(stop, dir, state)
+                    .iter()
+                    .map(|&(index, _, _)| self.position(index))
+                    .collect();
+
+                break Some(Cycle::new(turn_points));
+            }
+
+            pos = stop;
+
+            match rule.on_obstacle(dir) {
+                Reaction::Turn(new_dir) => dir = new_dir,
+                Reaction::PassThrough => {
+                    // obstacles never sit on the border, so there always is a tile behind them
+                    let obstacle = self.position(stop).step(dir);
+                    pos = self.index(obstacle);
+                }
+            }
+        }
+    }
+}
+
+/// A loop the guard is stuck in, described by the tiles at which it turns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cycle {
+    pub turn_points: Vec<Position>,
+    pub length: usize,
+}
+
+impl Cycle {
+    pub fn new(turn_points: Vec<Position>) -> Self {
+        // the guard walks in straight lines between the turn points and back to the first one
+        let length = turn_points
+            .iter()
+            .zip(turn_points.iter().cycle().skip(1))
+            .map(|(from, to)| from.x.abs_diff(to.x) + from.y.abs_diff(to.y))
+            .sum();
+
+        Self {
+            turn_points,
+            length,
+        }
+    }
+}
+
+/// Remembers at which tiles, in which directions and in which rule states the guard
+/// faced an obstacle, clearing only touches the tiles that were recorded.
+#[derive(Debug, Clone)]
+struct TurnLog {
+    masks: Vec<u32>,
+    turns: Vec<(usize, Direction, u8)>,
+}
+
+impl TurnLog {
+    pub fn new(map: &Map) -> Self {
+        Self {
+            masks: vec![0; map.width * map.height],
+            turns: Vec::new(),
+        }
+    }
+
+    /// Records a turn, returns `false` if the turn has been recorded before.
+    pub fn insert(&mut self, index: usize, dir: Direction, state: u8) -> bool {
+        debug_assert!(state < MAX_RULE_STATES, "rule state is out of range");
+
+        let bit = 1 << (state as usize * 4 + dir.index());
+        let mask = &mut self.masks[index];
+
+        if *mask & bit != 0 {
+            return false;
+        }
+
+        *mask |= bit;
+        self.turns.push((index, dir, state));
+        true
+    }
+
+    /// Returns all turns starting at the first time the given turn was recorded.
+    pub fn since(&self, index: usize, dir: Direction, state: u8) -> &[(usize, Direction, u8)] {
+        let start = self
+            .turns
+            .iter()
+            .position(|&turn| turn == (index, dir, state))
+            .unwrap_or(self.turns.len());
 
-    let width = 2 + input
+        &self.turns[start..]
+    }
+
+    pub fn clear(&mut self) {
+        for (index, _, _) in self.turns.drain(..) {
+            self.masks[index] = 0;
+        }
+    }
+}
+
+/// Walks the original route and returns every tile in the order the guard first
+/// reaches it, together with the position, direction and rule state right before
+/// entering it. An obstacle placed on a tile cannot change the route up to that point.
+fn first_visits(
+    map: &Map,
+    start_pos: Position,
+    start_dir: Direction,
+    rule: &mut dyn MovementRule,
+) -> Vec<(Position, (Position, Direction, u8))> {
+    let mut seen = vec![false; map.width * map.height];
+    let mut turns = TurnLog::new(map);
+    let mut visits = Vec::new();
+
+    let mut pos = start_pos;
+    let mut dir = start_dir;
+
+    rule.set_state(0);
+    seen[pos.y * map.width + pos.x] = true;
+
+    loop {
+        let next = pos.step(dir);
+
+        match map.get_tile(next) {
+            Tile::Obstacle => {
+                if !turns.insert(pos.y * map.width + pos.x, dir, rule.state()) {
+                    // the original route is already a loop
+                    break;
+                }
+
+                match rule.on_obstacle(dir) {
+                    Reaction::Turn(new_dir) => dir = new_dir,
+                    // obstacles are never recorded, an obstruction cannot be placed on them
+                    Reaction::PassThrough => pos = next,
+                }
+            }
+            Tile::Outside => break,
+            _ => {
+                let seen = &mut seen[next.y * map.width + next.x];
+
+                if !*seen {
+                    *seen = true;
+                    visits.push((next, (pos, dir, rule.state())));
+                }
+
+                pos = next;
+            }
+        }
+    }
+
+    visits
+}
+
+/// Finds every obstruction that makes one of the guards loop, together with the index
+/// of the guard and the cycle it ends up in. `guard_loops` tells which guards already
+/// loop on the original map.
+fn loop_obstructions(
+    map: &Map,
+    guards: &[(Position, Direction)],
+    guard_loops: &[bool],
+    rule: &mut dyn MovementRule,
+) -> Vec<(usize, Position, Cycle)> {
+    let mut jump_table = JumpTable::new(map);
+    let mut turns = TurnLog::new(map);
+
+    let mut loops = Vec::new();
+
+    for (guard_index, (&(start_pos, start_dir), &is_loop)) in
+        guards.iter().zip(guard_loops).enumerate()
+    {
+        // a guard that loops anyway cannot be made to loop by an obstruction
+        if is_loop {
+            continue;
+        }
+
+        // only tiles on the original path can change the guards route, the guard
+        // is resumed right in front of the new obstacle
+        let guard_obstructions = first_visits(map, start_pos, start_dir, rule)
+            .into_iter()
+            // cannot place obstacle at any start position
+            .filter(|&(position, _)| guards.iter().all(|&(pos, _)| pos != position))
+            .filter_map(|(position, resume)| {
+                jump_table.place_obstacle(map, position);
+                let cycle = jump_table.simulate(resume, rule, &mut turns);
+                jump_table.remove_obstacles();
+
+                cycle.map(|cycle| (guard_index, position, cycle))
+            });
+
+        loops.extend(guard_obstructions);
+    }
+
+    loops
+}
+
+/// Renders the map like the puzzle does, with the guards at their start positions
+/// and every given obstruction as `O`.
+fn render(map: &Map, guards: &[(Position, Direction)], obstructions: &[Position]) -> String {
+    let mut output = String::new();
+
+    // skip the border of outside tiles that is added while loading
+    for y in 1..map.height - 1 {
+        for x in 1..map.width - 1 {
+            let pos = Position { x, y };
+
+            let guard = guards.iter().find(|&&(start_pos, _)| start_pos == pos);
+
+            let ch = if let Some(&(_, dir)) = guard {
+                dir.glyph()
+            } else if obstructions.contains(&pos) {
+                'O'
+            } else if map.get_tile(pos) == Tile::Obstacle {
+                '#'
+            } else {
+                '.'
+            };
+
+            output.push(ch);
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Renders the tiles visited on the map with `|`, `-` or `+` depending on the directions
+/// they were walked in, the guard is drawn at its current position if it is on the map.
+fn render_path(map: &Map, guard: Option<(Position, Direction)>) -> String {
+    let vertical = Direction::Up.mask() | Direction::Down.mask();
+    let horizontal = Direction::Left.mask() | Direction::Right.mask();
+
+    let mut output = String::new();
+
+    for y in 1..map.height - 1 {
+        for x in 1..map.width - 1 {
+            let pos = Position { x, y };
+
+            let ch = match (guard, map.get_tile(pos)) {
+                (Some((guard_pos, dir)), _) if guard_pos == pos => dir.glyph(),
+                (_, Tile::Obstacle) => '#',
+                (_, Tile::Visited(dirs)) => match (dirs & vertical != 0, dirs & horizontal != 0) {
+                    (true, true) => '+',
+                    (true, false) => '|',
+                    _ => '-',
+                },
+                _ => '.',
+            };
+
+            output.push(ch);
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Writes the walk of every guard as a sequence of text frames, one frame each time
+/// a guard changes its direction or stops, followed by a summary frame showing the
+/// tiles covered by all guards.
+fn export_walk(
+    writer: &mut impl Write,
+    map: &Map,
+    guards: &[(Position, Direction)],
+    rule: &mut dyn MovementRule,
+) -> io::Result<()> {
+    let mut coverage = map.clone();
+    let mut frame = 0;
+
+    for (guard_index, &(start_pos, start_dir)) in guards.iter().enumerate() {
+        let mut guard_map = map.clone();
+        let mut guard = Guard::new(&mut guard_map, start_pos, start_dir, rule);
+        let mut steps = 0;
+
+        loop {
+            let previous_dir = guard.dir;
+            let result = guard.step();
+            steps += 1;
+
+            let status = match result {
+                StepResult::Step if guard.dir == previous_dir => continue,
+                StepResult::Step => "turned",
+                StepResult::Finish => "left the map",
+                StepResult::Loop => "is stuck in a loop",
+            };
+
+            frame += 1;
+            writeln!(
+                writer,
+                "Frame {frame}: guard {} {status} after {steps} steps",
+                guard_index + 1
+            )?;
+            writeln!(
+                writer,
+                "{}",
+                render_path(guard.map, Some((guard.pos, guard.dir)))
+            )?;
+
+            if !matches!(result, StepResult::Step) {
+                break;
+            }
+        }
+
+        coverage.merge_visited(&guard_map);
+    }
+
+    writeln!(
+        writer,
+        "Summary: {} tiles visited",
+        coverage.count_visited()
+    )?;
+    writeln!(writer, "{}", render_path(&coverage, None))?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LoadError {
+    EmptyMap,
+    UnevenRow { y: usize, width: usize },
+    UnknownTile { ch: char, x: usize, y: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::EmptyMap => write!(f, "the map is empty"),
+            LoadError::UnevenRow { y, width } => {
+                write!(f, "row {y} is {width} tiles wide, unlike the first row")
+            }
+            LoadError::UnknownTile { ch, x, y } => write!(f, "unknown tile {ch:?} at ({x},{y})"),
+        }
+    }
+}
+
+fn load() -> Result<(Map, Vec<(Position, Direction)>), LoadError> {
+    parse(include_str!("input.txt"))
+}
+
+fn parse(input: &str) -> Result<(Map, Vec<(Position, Direction)>), LoadError> {
+    let lines: Vec<&str> = input
         .lines()
-        .nth(0)
         .map(str::trim)
-        .map(|line| line.chars().count())
-        .unwrap();
-
-    let guard_x = AtomicUsize::new(0);
-    let guard_y = AtomicUsize::new(0);
-
-    let guard_x_ref = &guard_x;
-    let guard_y_ref = &guard_y;
-
-    let tile_iter = repeat(Tile::Outside)
-        .take(width)
-        .chain(
-            input
-                .lines()
-                .map(str::trim)
-                .filter(|line| !line.is_empty())
-                .enumerate()
-                .map(|(y, line)| {
-                    let line_tiles = line.chars().enumerate().map(move |(x, ch)| match ch {
-                        '.' => Tile::Empty,
-                        '^' => {
-                            guard_x_ref.store(x + 1, Ordering::Relaxed);
-                            guard_y_ref.store(y + 1, Ordering::Relaxed);
-                            Tile::Empty
-                        }
-                        '#' => Tile::Obstacle,
-                        _ => unreachable!(),
-                    });
-
-                    once(Tile::Outside)
-                        .chain(line_tiles)
-                        .chain(once(Tile::Outside))
-                })
-                .flatten(),
-        )
-        .chain(repeat(Tile::Outside).take(width));
-
-    let map = Map::new(tile_iter.collect(), width, height);
-    let guard_position = Position {
-        x: guard_x.into_inner(),
-        y: guard_y.into_inner(),
-    };
-
-    (map, guard_position)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    let first_line = lines.first().ok_or(LoadError::EmptyMap)?;
+
+    let height = 2 + lines.len();
+    let width = 2 + first_line.chars().count();
+
+    let mut tiles = Vec::with_capacity(width * height);
+    let mut guards = Vec::new();
+
+    tiles.extend(repeat_n(Tile::Outside, width));
+
+    for (y, line) in lines.iter().enumerate() {
+        tiles.push(Tile::Outside);
+
+        for (x, ch) in line.chars().enumerate() {
+            let tile = match ch {
+                '.' => Tile::Empty,
+                '#' => Tile::Obstacle,
+                _ => {
+                    let dir =
+                        Direction::from_glyph(ch).ok_or(LoadError::UnknownTile { ch, x, y })?;
+                    guards.push((Position { x: x + 1, y: y + 1 }, dir));
+
+                    Tile::Empty
+                }
+            };
+
+            tiles.push(tile);
+        }
+
+        tiles.push(Tile::Outside);
+
+        if tiles.len() != (y + 2) * width {
+            return Err(LoadError::UnevenRow {
+                y,
+                width: line.chars().count(),
+            });
+        }
+    }
+
+    tiles.extend(repeat_n(Tile::Outside, width));
+
+    Ok((Map::new(tiles, width, height), guards))
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let render_mode = args.iter().any(|arg| arg == "--render");
+
+    let rule_name = args
+        .iter()
+        .position(|arg| arg == "--rule")
+        .and_then(|index| args.get(index + 1))
+        .map_or("right", String::as_str);
+
+    let export_path = args
+        .iter()
+        .position(|arg| arg == "--export")
+        .and_then(|index| args.get(index + 1));
+
+    let mut rule = movement_rule(rule_name).unwrap_or_else(|| {
+        eprintln!("error: unknown movement rule {rule_name:?}");
+        process::exit(1);
+    });
+
     let load_start = Instant::now();
 
-    let (mut map, start_pos) = load();
+    let (map, guards) = load().unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        process::exit(1);
+    });
 
     let load_elapsed = load_start.elapsed();
 
@@ -304,11 +932,21 @@ fn main() {
 
     let part_1_start = Instant::now();
 
-    // simulate the guard on our map
-    let mut guard = Guard::new(&mut map, start_pos, Direction::Up);
-    guard.simulate();
+    // simulate every guard on its own copy of the map, so they cannot mistake
+    // each others paths for a loop, and combine the visited tiles afterwards
+    let mut visited_map = map.clone();
+    let mut guard_loops = Vec::with_capacity(guards.len());
 
-    let visited_count = map.count_visited();
+    for &(start_pos, start_dir) in &guards {
+        let mut guard_map = map.clone();
+
+        let mut guard = Guard::new(&mut guard_map, start_pos, start_dir, rule.as_mut());
+        guard_loops.push(guard.simulate());
+
+        visited_map.merge_visited(&guard_map);
+    }
+
+    let visited_count = visited_map.count_visited();
 
     let part_1_elapsed = part_1_start.elapsed();
 
@@ -318,64 +956,15 @@ fn main() {
 
     let part_2_start = Instant::now();
 
-    // dedupe visited fields
-    let mut visited_fields = map.visited_iter().collect::<Vec<_>>();
-    let visited_fields_set: HashSet<_> = visited_fields.drain(..).collect();
-    visited_fields.extend(visited_fields_set.into_iter());
-
-    map.reset();
-
-    // calculate chunk size for n threads
-    let thread_count = 16;
-    let chunk_size = match visited_fields.len() % thread_count {
-        0 => visited_fields.len() / thread_count,
-        left_over => (visited_fields.len() + thread_count - left_over) / thread_count,
-    };
-
-    // each thread checks its part of the chunks for loops
-    let mut handles = Vec::new();
-    for chunk in visited_fields.chunks(chunk_size) {
-        let mut thread_map = map.clone();
-        let chunk = chunk.into_iter().copied().collect::<Vec<_>>();
-
-        let handle = thread::spawn(move || {
-            let mut loop_count: usize = 0;
-
-            for position in chunk {
-                // cannot place obstacle at start position
-                if position == start_pos {
-                    continue;
-                }
-
-                // change tile
-                let previous = thread_map.replace_tile(position, Tile::Obstacle);
-
-                // check if the guard loops
-                let mut guard = Guard::new(&mut thread_map, start_pos, Direction::Up);
-                if guard.simulate() {
-                    loop_count += 1;
-                }
-
-                // revert tile change
-                thread_map.set_tile(position, previous);
-                thread_map.reset();
-            }
-
-            loop_count
-        });
-
-        handles.push(handle);
-    }
+    let loops = loop_obstructions(&map, &guards, &guard_loops, rule.as_mut());
 
-    // calculate sum of loops
-    let loop_count: usize = handles
-        .into_iter()
-        .map(|handle| handle.join().expect("failed to join handle"))
-        .sum();
+    let mut obstructions: Vec<Position> = loops.iter().map(|&(_, position, _)| position).collect();
+    obstructions.sort_unstable_by_key(|pos| (pos.y, pos.x));
+    obstructions.dedup();
 
     let part_2_elapsed = part_2_start.elapsed();
 
-    println!("Part 2: {}", loop_count);
+    println!("Part 2: {}", obstructions.len());
     println!("  Time: {:?}", part_2_elapsed);
     println!();
 
@@ -383,4 +972,247 @@ fn main() {
         "Total: {:?}",
         (part_2_elapsed) + (part_1_elapsed) + (load_elapsed)
     );
+
+    if guards.len() > 1 {
+        println!();
+
+        for (guard_index, (&(start_pos, start_dir), is_loop)) in
+            guards.iter().zip(&guard_loops).enumerate()
+        {
+            println!(
+                "Guard {} {} at ({},{}): {}",
+                guard_index + 1,
+                start_dir.glyph(),
+                start_pos.x - 1,
+                start_pos.y - 1,
+                if *is_loop { "loops" } else { "leaves the map" }
+            );
+        }
+    }
+
+    if render_mode {
+        println!();
+
+        for (guard_index, position, cycle) in &loops {
+            // positions are shifted by the border of outside tiles
+            let turn_points = cycle
+                .turn_points
+                .iter()
+                .map(|pos| format!("({},{})", pos.x - 1, pos.y - 1))
+                .collect::<Vec<_>>()
+                .join(" -> ");
+
+            println!(
+                "Obstruction ({},{}) for guard {}:",
+                position.x - 1,
+                position.y - 1,
+                guard_index + 1
+            );
+            println!("  Cycle: {}", turn_points);
+            println!(" Length: {}", cycle.length);
+            println!();
+        }
+
+        print!("{}", render(&map, &guards, &obstructions));
+    }
+
+    if let Some(export_path) = export_path {
+        let result = File::create(export_path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            export_walk(&mut writer, &map, &guards, rule.as_mut())?;
+            writer.flush()
+        });
+
+        if let Err(err) = result {
+            eprintln!("error: failed to export walk to {export_path:?}: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: [&str; 5] = ["right", "left", "around", "alternate", "pass-through"];
+
+    fn guard_loops(
+        map: &Map,
+        (pos, dir): (Position, Direction),
+        rule: &mut dyn MovementRule,
+    ) -> bool {
+        let mut guard_map = map.clone();
+
+        Guard::new(&mut guard_map, pos, dir, rule).simulate()
+    }
+
+    /// Checks the guard and the jump table agree on whether the single guard of the map
+    /// loops, and returns the answer.
+    fn loops(input: &str, rule_name: &str) -> bool {
+        let (map, guards) = parse(input).unwrap();
+        let (pos, dir) = guards[0];
+        let mut rule = movement_rule(rule_name).unwrap();
+
+        let is_loop = guard_loops(&map, (pos, dir), rule.as_mut());
+        let cycle =
+            JumpTable::new(&map).simulate((pos, dir, 0), rule.as_mut(), &mut TurnLog::new(&map));
+
+        assert_eq!(
+            is_loop,
+            cycle.is_some(),
+            "{rule_name} guard and jump table disagree"
+        );
+        is_loop
+    }
+
+    /// Places an obstruction on every empty tile and lets the guard walk the whole map.
+    fn brute_force_obstructions(
+        map: &Map,
+        guard: (Position, Direction),
+        rule: &mut dyn MovementRule,
+    ) -> usize {
+        let start = guard.0.y * map.width + guard.0.x;
+
+        (0..map.tiles.len())
+            .filter(|&index| index != start && map.tiles[index] == Tile::Empty)
+            .filter(|&index| {
+                let mut obstructed = map.clone();
+                obstructed.tiles[index] = Tile::Obstacle;
+
+                guard_loops(&obstructed, guard, rule)
+            })
+            .count()
+    }
+
+    fn assert_obstructions_match_brute_force(input: &str) {
+        let (map, guards) = parse(input).unwrap();
+
+        for rule_name in RULES {
+            let mut rule = movement_rule(rule_name).unwrap();
+
+            // every tile would count for a guard that loops anyway
+            if guard_loops(&map, guards[0], rule.as_mut()) {
+                continue;
+            }
+
+            assert_eq!(
+                loop_obstructions(&map, &guards, &[false], rule.as_mut()).len(),
+                brute_force_obstructions(&map, guards[0], rule.as_mut()),
+                "obstructions for {rule_name} disagree"
+            );
+        }
+    }
+
+    #[test]
+    fn turn_right() {
+        assert!(loops(".#...\n....#\n.....\n#^...\n...#.", "right"));
+        assert!(!loops("...\n.^.\n...", "right"));
+    }
+
+    #[test]
+    fn turn_left() {
+        assert!(loops("...#.\n#....\n.....\n...^#\n.#...", "left"));
+        assert!(!loops(".#...\n....#\n.....\n#^...\n...#.", "left"));
+    }
+
+    #[test]
+    fn turn_around() {
+        assert!(loops("#.\n..\n^.\n#.", "around"));
+        assert!(!loops("..\n^.\n#.", "around"));
+    }
+
+    #[test]
+    fn alternate_turns() {
+        // left into the wall, right into the ceiling and left again
+        assert!(loops(".#.\n#^.", "alternate"));
+        // turning left and right in turn always leads up and left
+        assert!(!loops(".#...\n....#\n.....\n#^...\n...#.", "alternate"));
+    }
+
+    #[test]
+    fn pass_through_once() {
+        assert!(loops(
+            ".#...\n....#\n.....\n#....\n.#.#.\n.^...",
+            "pass-through"
+        ));
+        assert!(!loops(".#.\n...\n.^.", "pass-through"));
+        // the first obstacle is passed instead of closing the loop
+        assert!(!loops(".#...\n....#\n.....\n#^...\n...#.", "pass-through"));
+    }
+
+    #[test]
+    fn example() {
+        let (map, guards) = parse(include_str!("example.txt")).unwrap();
+        let mut rule = TurnRight;
+
+        assert_eq!(
+            loop_obstructions(&map, &guards, &[false], &mut rule).len(),
+            6
+        );
+    }
+
+    #[test]
+    fn obstructions_match_brute_force_on_example() {
+        assert_obstructions_match_brute_force(include_str!("example.txt"));
+    }
+
+    #[test]
+    fn obstructions_match_brute_force_on_small_map() {
+        // every rule leaves this map, and every rule has obstructions that make it loop
+        assert_obstructions_match_brute_force(
+            "..........
+            .........#
+            ........#.
+            .#........
+            ...#......
+            ....#..##.
+            #.#.......
+            ..##^.#.#.
+            .#...#....
+            ..........",
+        );
+    }
+
+    #[test]
+    #[ignore = "brute forces every rule over the whole input, run with `cargo test -- --ignored`"]
+    fn obstructions_match_brute_force_on_input() {
+        assert_obstructions_match_brute_force(include_str!("input.txt"));
+    }
+
+    #[test]
+    fn guards_face_every_direction() {
+        let (_, guards) = parse(">.\n.<\nv^").unwrap();
+
+        // positions are shifted by the border of outside tiles
+        assert_eq!(
+            guards,
+            [
+                (Position { x: 1, y: 1 }, Direction::Right),
+                (Position { x: 2, y: 2 }, Direction::Left),
+                (Position { x: 1, y: 3 }, Direction::Down),
+                (Position { x: 2, y: 3 }, Direction::Up),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_maps_are_reported() {
+        assert_eq!(parse("").unwrap_err(), LoadError::EmptyMap);
+        assert_eq!(
+            parse("..x\n").unwrap_err(),
+            LoadError::UnknownTile {
+                ch: 'x',
+                x: 2,
+                y: 0
+            }
+        );
+        assert_eq!(
+            parse("...\n..\n...").unwrap_err(),
+            LoadError::UnevenRow { y: 1, width: 2 }
+        );
+        assert_eq!(
+            parse("...\n....").unwrap_err(),
+            LoadError::UnevenRow { y: 1, width: 4 }
+        );
+    }
 }
@@ -1,8 +1,18 @@
-use std::{mem, thread, time::Instant};
+use std::{env, process, time::Instant};
 
-fn load() -> Vec<(usize, Vec<usize>)> {
-    let input = include_str!("input.txt");
+/// The integer type all equations are evaluated in, building with the `wide` feature
+/// switches to `u128` for goals or intermediate results that do not fit into 64 bits.
+#[cfg(not(feature = "wide"))]
+type Value = u64;
 
+#[cfg(feature = "wide")]
+type Value = u128;
+
+fn load() -> Vec<(Value, Vec<Value>)> {
+    parse(include_str!("input.txt"))
+}
+
+fn parse(input: &str) -> Vec<(Value, Vec<Value>)> {
     input
         .lines()
         .map(str::trim)
@@ -10,12 +20,12 @@ fn load() -> Vec<(usize, Vec<usize>)> {
         .map(|line| {
             let (goal, numbers_str) = line.split_once(":").expect("failed to parse input");
 
-            let goal = goal.parse::<usize>().expect("failed to parse number");
+            let goal = goal.parse::<Value>().expect("failed to parse number");
 
             let numbers = numbers_str
                 .trim()
                 .split(" ")
-                .map(|num| num.parse::<usize>().expect("failed to parse number"))
+                .map(|num| num.parse::<Value>().expect("failed to parse number"))
                 .collect();
 
             (goal, numbers)
@@ -23,55 +33,376 @@ fn load() -> Vec<(usize, Vec<usize>)> {
         .collect()
 }
 
-fn is_solvable(goal: usize, numbers: &Vec<usize>, use_concat_operator: bool) -> bool {
-    let mut numbers = numbers.iter().copied().peekable();
-    let mut queue = Vec::new();
+/// How the left hand side of an operation can be recovered from its result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Inverse {
+    /// No left hand side produces the result.
+    Impossible,
+    /// Exactly this left hand side produces the result.
+    Exact(Value),
+    /// Several left hand sides may produce the result, they have to be searched for.
+    Unknown,
+}
 
-    queue.push(numbers.next().expect("failed to unwrap first number"));
+/// A binary operator that combines the running value with the next number.
+trait Operator {
+    /// The name used to select the operator on the command line.
+    fn name(&self) -> &'static str;
 
-    while let Some(num) = numbers.next() {
-        let old_queue = mem::take(&mut queue);
-        let is_last = numbers.peek().is_none();
+    fn symbol(&self) -> &'static str;
 
-        for prev in old_queue {
-            let add = prev + num;
-            let mul = prev * num;
+    /// Returns `None` if the result is undefined or does not fit into a [`Value`].
+    fn apply(&self, lhs: Value, rhs: Value) -> Option<Value>;
 
-            if is_last && (add == goal || mul == goal) {
-                return true;
-            }
+    fn undo(&self, result: Value, rhs: Value) -> Inverse;
 
-            if use_concat_operator {
-                let num_digits = num.ilog10() + 1;
-                let concat = prev * 10usize.pow(num_digits) + num;
+    /// Returns `true` if the result is never smaller than the left hand side as long as
+    /// the right hand side is positive, which lets the forward solver in the tests prune
+    /// values that exceed the goal.
+    #[cfg(test)]
+    fn is_increasing(&self) -> bool {
+        false
+    }
+}
 
-                if is_last && concat == goal {
-                    return true;
-                }
+struct Add;
 
-                queue.push(concat);
-            }
+impl Operator for Add {
+    fn name(&self) -> &'static str {
+        "add"
+    }
+
+    fn symbol(&self) -> &'static str {
+        "+"
+    }
+
+    fn apply(&self, lhs: Value, rhs: Value) -> Option<Value> {
+        lhs.checked_add(rhs)
+    }
+
+    fn undo(&self, result: Value, rhs: Value) -> Inverse {
+        result
+            .checked_sub(rhs)
+            .map_or(Inverse::Impossible, Inverse::Exact)
+    }
+
+    #[cfg(test)]
+    fn is_increasing(&self) -> bool {
+        true
+    }
+}
+
+struct Mul;
+
+impl Operator for Mul {
+    fn name(&self) -> &'static str {
+        "mul"
+    }
+
+    fn symbol(&self) -> &'static str {
+        "*"
+    }
+
+    fn apply(&self, lhs: Value, rhs: Value) -> Option<Value> {
+        lhs.checked_mul(rhs)
+    }
+
+    fn undo(&self, result: Value, rhs: Value) -> Inverse {
+        match (result, rhs) {
+            // multiplying by zero always results in zero, no matter what came before
+            (0, 0) => Inverse::Unknown,
+            (_, 0) => Inverse::Impossible,
+            _ if result.is_multiple_of(rhs) => Inverse::Exact(result / rhs),
+            _ => Inverse::Impossible,
+        }
+    }
+
+    #[cfg(test)]
+    fn is_increasing(&self) -> bool {
+        true
+    }
+}
+
+/// Appends the digits of the right hand side to the left hand side.
+struct Concat;
+
+impl Operator for Concat {
+    fn name(&self) -> &'static str {
+        "concat"
+    }
+
+    fn symbol(&self) -> &'static str {
+        "||"
+    }
+
+    fn apply(&self, lhs: Value, rhs: Value) -> Option<Value> {
+        let shift = Value::checked_pow(10, rhs.checked_ilog10().unwrap_or(0) + 1)?;
+
+        lhs.checked_mul(shift)?.checked_add(rhs)
+    }
+
+    fn undo(&self, result: Value, rhs: Value) -> Inverse {
+        // a shift that does not fit is rejected by `apply` as well, even for a zero
+        // left hand side
+        match Value::checked_pow(10, rhs.checked_ilog10().unwrap_or(0) + 1) {
+            Some(shift) if result % shift == rhs => Inverse::Exact(result / shift),
+            _ => Inverse::Impossible,
+        }
+    }
+
+    #[cfg(test)]
+    fn is_increasing(&self) -> bool {
+        true
+    }
+}
+
+struct Sub;
+
+impl Operator for Sub {
+    fn name(&self) -> &'static str {
+        "sub"
+    }
+
+    fn symbol(&self) -> &'static str {
+        "-"
+    }
+
+    fn apply(&self, lhs: Value, rhs: Value) -> Option<Value> {
+        lhs.checked_sub(rhs)
+    }
+
+    fn undo(&self, result: Value, rhs: Value) -> Inverse {
+        result
+            .checked_add(rhs)
+            .map_or(Inverse::Impossible, Inverse::Exact)
+    }
+}
+
+/// Integer division that rounds towards zero.
+struct Div;
+
+impl Operator for Div {
+    fn name(&self) -> &'static str {
+        "div"
+    }
+
+    fn symbol(&self) -> &'static str {
+        "/"
+    }
+
+    fn apply(&self, lhs: Value, rhs: Value) -> Option<Value> {
+        lhs.checked_div(rhs)
+    }
+
+    fn undo(&self, _result: Value, _rhs: Value) -> Inverse {
+        Inverse::Unknown
+    }
+}
+
+struct Xor;
+
+impl Operator for Xor {
+    fn name(&self) -> &'static str {
+        "xor"
+    }
+
+    fn symbol(&self) -> &'static str {
+        "^"
+    }
+
+    fn apply(&self, lhs: Value, rhs: Value) -> Option<Value> {
+        Some(lhs ^ rhs)
+    }
+
+    fn undo(&self, result: Value, rhs: Value) -> Inverse {
+        Inverse::Exact(result ^ rhs)
+    }
+}
+
+const OPERATORS: &[&dyn Operator] = &[&Add, &Mul, &Concat, &Sub, &Div, &Xor];
+
+/// Parses a comma separated list of operator names like `add,mul,concat`.
+fn parse_operators(names: &str) -> Result<Vec<&'static dyn Operator>, String> {
+    names
+        .split(',')
+        .map(str::trim)
+        .map(|name| {
+            OPERATORS
+                .iter()
+                .copied()
+                .find(|operator| operator.name() == name)
+                .ok_or_else(|| format!("unknown operator {name:?}"))
+        })
+        .collect()
+}
+
+/// Evaluates every operator combination from left to right and calls `accept` with
+/// each result, stops as soon as `accept` returns `true`. The operators leading to the
+/// accepted result are left in `path`. Branches whose value exceeds `bound` are pruned,
+/// callers have to make sure no operator can decrease the value again.
+fn any_result(
+    value: Value,
+    numbers: &[Value],
+    operators: &[&'static dyn Operator],
+    bound: Option<Value>,
+    path: &mut Vec<&'static dyn Operator>,
+    accept: &mut dyn FnMut(Value) -> bool,
+) -> bool {
+    if bound.is_some_and(|bound| value > bound) {
+        return false;
+    }
+
+    let Some((&num, rest)) = numbers.split_first() else {
+        return accept(value);
+    };
+
+    for &operator in operators {
+        let Some(next) = operator.apply(value, num) else {
+            continue;
+        };
+
+        path.push(operator);
 
-            queue.push(prev + num);
-            queue.push(prev * num);
+        if any_result(next, rest, operators, bound, path, accept) {
+            return true;
         }
+
+        path.pop();
     }
 
     false
 }
 
+/// Works backwards from the goal by undoing the last operation, a branch is pruned as
+/// soon as no operator can have produced the goal from the last number. Returns the
+/// operators of the first solution that is found.
+fn find_solution(
+    goal: Value,
+    numbers: &[Value],
+    operators: &[&'static dyn Operator],
+) -> Option<Vec<&'static dyn Operator>> {
+    let (&last, rest) = numbers.split_last()?;
+
+    if rest.is_empty() {
+        return (goal == last).then(Vec::new);
+    }
+
+    operators.iter().find_map(|&operator| {
+        let mut solution = match operator.undo(goal, last) {
+            Inverse::Impossible => return None,
+            Inverse::Exact(prev) => find_solution(prev, rest, operators)?,
+            Inverse::Unknown => {
+                let (&first, middle) = rest.split_first().expect("rest is not empty");
+                let mut path = Vec::new();
+
+                any_result(first, middle, operators, None, &mut path, &mut |prev| {
+                    operator.apply(prev, last) == Some(goal)
+                })
+                .then_some(path)?
+            }
+        };
+
+        solution.push(operator);
+        Some(solution)
+    })
+}
+
+fn is_solvable(goal: Value, numbers: &[Value], operators: &[&'static dyn Operator]) -> bool {
+    find_solution(goal, numbers, operators).is_some()
+}
+
+/// Counts every distinct sequence of operators that solves the equation.
+fn count_solutions(goal: Value, numbers: &[Value], operators: &[&'static dyn Operator]) -> usize {
+    let Some((&last, rest)) = numbers.split_last() else {
+        return 0;
+    };
+
+    if rest.is_empty() {
+        return (goal == last) as usize;
+    }
+
+    operators
+        .iter()
+        .map(|&operator| match operator.undo(goal, last) {
+            Inverse::Impossible => 0,
+            Inverse::Exact(prev) => count_solutions(prev, rest, operators),
+            Inverse::Unknown => {
+                let (&first, middle) = rest.split_first().expect("rest is not empty");
+                let mut count = 0;
+
+                // never accept a result, so every combination is visited
+                any_result(
+                    first,
+                    middle,
+                    operators,
+                    None,
+                    &mut Vec::new(),
+                    &mut |prev| {
+                        if operator.apply(prev, last) == Some(goal) {
+                            count += 1;
+                        }
+
+                        false
+                    },
+                );
+
+                count
+            }
+        })
+        .sum()
+}
+
+/// Renders a solution the way the puzzle does, e.g. `3267: 81 + 40 * 27`.
+fn render_solution(goal: Value, numbers: &[Value], solution: &[&dyn Operator]) -> String {
+    let mut output = format!("{goal}: {}", numbers[0]);
+
+    for (operator, num) in solution.iter().zip(&numbers[1..]) {
+        output.push_str(&format!(" {} {}", operator.symbol(), num));
+    }
+
+    output
+}
+
+/// Sums the goals of all solvable equations, `None` if the sum does not fit into a [`Value`].
+fn solve(equations: &[(Value, Vec<Value>)], operators: &[&'static dyn Operator]) -> Option<Value> {
+    equations
+        .iter()
+        .filter(|(goal, numbers)| is_solvable(*goal, numbers, operators))
+        .try_fold(0, |sum: Value, (goal, _)| sum.checked_add(*goal))
+}
+
+fn expect_sum(sum: Option<Value>) -> Value {
+    sum.unwrap_or_else(|| {
+        eprintln!("error: the sum of goals overflows, try building with `--features wide`");
+        process::exit(1);
+    })
+}
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // counting lists the solutions as well, so `--count` implies `--solutions`
+    let count_all = args.iter().any(|arg| arg == "--count");
+    let show_solutions = count_all || args.iter().any(|arg| arg == "--solutions");
+
+    let custom_operators = args
+        .iter()
+        .position(|arg| arg == "--operators")
+        .and_then(|index| args.get(index + 1))
+        .map(|names| {
+            parse_operators(names).unwrap_or_else(|err| {
+                eprintln!("error: {err}");
+                process::exit(1);
+            })
+        });
+
+    let part_1_operators: &[&'static dyn Operator] = &[&Add, &Mul];
+    let part_2_operators: &[&'static dyn Operator] = &[&Add, &Mul, &Concat];
+
     let load_start = Instant::now();
 
     let equations = load();
 
-    // calculate chunk size for n threads
-    let thread_count = 16;
-    let chunk_size = match equations.len() % thread_count {
-        0 => equations.len() / thread_count,
-        left_over => (equations.len() + thread_count - left_over) / thread_count,
-    };
-
     let load_elapsed = load_start.elapsed();
 
     println!("Loading:");
@@ -80,25 +411,7 @@ fn main() {
 
     let part_1_start = Instant::now();
 
-    let handles: Vec<_> = equations
-        .chunks(chunk_size)
-        .map(|chunk| {
-            let chunk = chunk.into_iter().cloned().collect::<Vec<_>>();
-
-            thread::spawn(move || {
-                chunk
-                    .iter()
-                    .filter(|(goal, numbers)| is_solvable(*goal, numbers, false))
-                    .map(|(goal, _)| goal)
-                    .sum::<usize>()
-            })
-        })
-        .collect();
-
-    let part_1_sum: usize = handles
-        .into_iter()
-        .map(|handle| handle.join().expect("failed to join handle"))
-        .sum();
+    let part_1_sum = expect_sum(solve(&equations, part_1_operators));
 
     let part_1_elapsed = part_1_start.elapsed();
 
@@ -108,25 +421,7 @@ fn main() {
 
     let part_2_start = Instant::now();
 
-    let handles: Vec<_> = equations
-        .chunks(chunk_size)
-        .map(|chunk| {
-            let chunk = chunk.into_iter().cloned().collect::<Vec<_>>();
-
-            thread::spawn(move || {
-                chunk
-                    .iter()
-                    .filter(|(goal, numbers)| is_solvable(*goal, numbers, true))
-                    .map(|(goal, _)| goal)
-                    .sum::<usize>()
-            })
-        })
-        .collect();
-
-    let part_2_sum: usize = handles
-        .into_iter()
-        .map(|handle| handle.join().expect("failed to join handle"))
-        .sum();
+    let part_2_sum = expect_sum(solve(&equations, part_2_operators));
 
     let part_2_elapsed = part_2_start.elapsed();
 
@@ -138,4 +433,211 @@ fn main() {
         "  Total: {:?}",
         (part_2_elapsed) + (part_1_elapsed) + (load_elapsed)
     );
+
+    if let Some(operators) = &custom_operators {
+        let custom_start = Instant::now();
+
+        let custom_sum = expect_sum(solve(&equations, operators));
+
+        let custom_elapsed = custom_start.elapsed();
+
+        let symbols = operators
+            .iter()
+            .map(|operator| operator.symbol())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        println!();
+        println!(" Custom: {} ({})", custom_sum, symbols);
+        println!("   Time: {:?}", custom_elapsed);
+    }
+
+    if show_solutions {
+        let operators = custom_operators.as_deref().unwrap_or(part_2_operators);
+
+        println!();
+
+        for (goal, numbers) in &equations {
+            let Some(solution) = find_solution(*goal, numbers, operators) else {
+                continue;
+            };
+
+            if count_all {
+                let count = count_solutions(*goal, numbers, operators);
+
+                println!(
+                    "{} ({} solution{})",
+                    render_solution(*goal, numbers, &solution),
+                    count,
+                    if count == 1 { "" } else { "s" }
+                );
+            } else {
+                println!("{}", render_solution(*goal, numbers, &solution));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expands every operator combination from left to right, the backward search has
+    /// to agree with it on every equation.
+    fn is_solvable_forward(
+        goal: Value,
+        numbers: &[Value],
+        operators: &[&'static dyn Operator],
+    ) -> bool {
+        let Some((&first, rest)) = numbers.split_first() else {
+            return false;
+        };
+
+        // overflowing operations are already pruned by `apply`, values above the goal
+        // can be dropped too if they can never shrink again
+        let can_prune = operators.iter().all(|operator| operator.is_increasing())
+            && numbers.iter().all(|&num| num > 0);
+
+        any_result(
+            first,
+            rest,
+            operators,
+            can_prune.then_some(goal),
+            &mut Vec::new(),
+            &mut |value| value == goal,
+        )
+    }
+
+    const PART_1: &[&dyn Operator] = &[&Add, &Mul];
+    const PART_2: &[&dyn Operator] = &[&Add, &Mul, &Concat];
+
+    fn assert_solvers_agree(
+        equations: &[(Value, Vec<Value>)],
+        operators: &[&'static dyn Operator],
+    ) {
+        for (goal, numbers) in equations {
+            assert_eq!(
+                is_solvable(*goal, numbers, operators),
+                is_solvable_forward(*goal, numbers, operators),
+                "solvers disagree on {goal}: {numbers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn example() {
+        let equations = parse(include_str!("example.txt"));
+
+        assert_eq!(solve(&equations, PART_1), Some(3749));
+        assert_eq!(solve(&equations, PART_2), Some(11387));
+    }
+
+    #[test]
+    fn solvers_agree_on_example() {
+        let equations = parse(include_str!("example.txt"));
+
+        assert_solvers_agree(&equations, PART_1);
+        assert_solvers_agree(&equations, PART_2);
+        assert_solvers_agree(&equations, OPERATORS);
+    }
+
+    #[test]
+    fn solvers_agree_on_input() {
+        let equations = load();
+
+        assert_solvers_agree(&equations, PART_1);
+        assert_solvers_agree(&equations, PART_2);
+    }
+
+    #[test]
+    fn solvers_agree_on_zeros() {
+        // multiplying by zero hides the left hand side, so undoing it is never exact
+        let equations = parse(
+            "0: 0 0
+            0: 5 0
+            5: 5 0
+            0: 0 7
+            7: 0 7
+            0: 3 4 0
+            12: 3 4 0
+            50: 5 0
+            0: 0 0 0
+            10: 1 0 0",
+        );
+
+        assert_solvers_agree(&equations, PART_1);
+        assert_solvers_agree(&equations, PART_2);
+        assert_solvers_agree(&equations, OPERATORS);
+    }
+
+    #[cfg(not(feature = "wide"))]
+    #[test]
+    fn concat_undo_matches_apply_when_the_shift_overflows() {
+        let goal = 10_000_000_000_000_000_000;
+
+        assert_eq!(Concat.apply(0, goal), None);
+        assert_eq!(Concat.undo(goal, goal), Inverse::Impossible);
+        assert_solvers_agree(&[(goal, vec![0, goal])], &[&Concat]);
+    }
+
+    /// Every equation in the fixture has intermediate results that do not fit into
+    /// 64 bits on at least one branch.
+    fn overflow_equations() -> Vec<(Value, Vec<Value>)> {
+        parse(include_str!("overflow_example.txt"))
+    }
+
+    #[test]
+    fn overflowing_branches_are_pruned() {
+        let solvable = |operators: &[&'static dyn Operator]| {
+            overflow_equations()
+                .iter()
+                .map(|(goal, numbers)| is_solvable(*goal, numbers, operators))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            solvable(PART_1),
+            [true, true, true, false, true, true, false]
+        );
+        assert_eq!(
+            solvable(PART_2),
+            [true, true, true, false, true, true, true]
+        );
+        assert_solvers_agree(&overflow_equations(), PART_2);
+    }
+
+    #[cfg(not(feature = "wide"))]
+    #[test]
+    fn overflowing_operators_return_none() {
+        assert_eq!(Mul.apply(4294967296, 4294967296), None);
+        assert_eq!(Mul.apply(4294967295, 4294967297), Some(u64::MAX));
+        assert_eq!(Concat.apply(4294967296, 4294967296), None);
+        assert_eq!(Concat.apply(1844674407370955161, 6), None);
+        assert_eq!(Concat.apply(1844674407370955161, 5), Some(u64::MAX));
+    }
+
+    #[cfg(not(feature = "wide"))]
+    #[test]
+    fn overflowing_sum_returns_none() {
+        assert_eq!(solve(&overflow_equations(), PART_1), None);
+        assert_eq!(solve(&overflow_equations(), PART_2), None);
+    }
+
+    #[cfg(feature = "wide")]
+    #[test]
+    fn wide_values_fit_the_overflowing_results() {
+        assert_eq!(Mul.apply(4294967296, 4294967296), Some(1 << 64));
+        assert_eq!(
+            Concat.apply(4294967296, 4294967296),
+            Some(42949672964294967296)
+        );
+        assert_eq!(
+            solve(&overflow_equations(), PART_1),
+            Some(36446744093709554884)
+        );
+        assert_eq!(
+            solve(&overflow_equations(), PART_2),
+            Some(36570200882833011673)
+        );
+    }
 }
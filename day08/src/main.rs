@@ -1,9 +1,13 @@
 use std::{
     collections::{HashMap, HashSet},
+    env, process,
+    str::FromStr,
     time::Instant,
 };
 
-fn load() -> (HashMap<char, Vec<(isize, isize)>>, (isize, isize)) {
+type Antennas = HashMap<char, Vec<(isize, isize)>>;
+
+fn load() -> (Antennas, (isize, isize)) {
     let input = include_str!("input.txt");
 
     let mut positions = HashMap::new();
@@ -22,13 +26,9 @@ fn load() -> (HashMap<char, Vec<(isize, isize)>>, (isize, isize)) {
             .enumerate()
             .filter(|(_, ch)| matches!(ch, 'a'..='z' | 'A'..='Z' | '0'..='9'))
         {
-            if !positions.contains_key(&ch) {
-                positions.insert(ch, Vec::new());
-            }
-
             positions
-                .get_mut(&ch)
-                .unwrap()
+                .entry(ch)
+                .or_insert_with(Vec::new)
                 .push((x as isize, y as isize));
         }
 
@@ -52,85 +52,305 @@ fn unique_pairs<T: Clone + Eq + std::hash::Hash, I: Iterator<Item = T>>(set: I)
     pairs
 }
 
-fn main() {
-    let load_start = Instant::now();
+fn gcd(a: isize, b: isize) -> isize {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
 
-    let (positions, (width, height)) = load();
+type Antinodes = HashMap<char, HashSet<(isize, isize)>>;
 
-    let load_elapsed = load_start.elapsed();
+fn in_bounds((x, y): (isize, isize), (width, height): (isize, isize)) -> bool {
+    x >= 0 && x < width && y >= 0 && y < height
+}
 
-    println!("Loading:");
-    println!("   Time: {:?}", load_elapsed);
-    println!();
+/// A point on the line through antennas `a` and `b` lies at `a + ratio * (b - a)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Ratio {
+    numerator: isize,
+    denominator: isize,
+}
 
-    let mut antinode_positions = HashSet::new();
+impl FromStr for Ratio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (numerator, denominator) = s.split_once('/').unwrap_or((s, "1"));
+
+        let numerator = numerator
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid ratio {s:?}"))?;
+        let denominator: isize = denominator
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid ratio {s:?}"))?;
+
+        if denominator <= 0 {
+            return Err(format!(
+                "invalid ratio {s:?}, the denominator has to be positive"
+            ));
+        }
 
-    let part_1_start = Instant::now();
+        Ok(Ratio {
+            numerator,
+            denominator,
+        })
+    }
+}
 
-    for (_, positions) in positions.iter() {
-        for ((ax, ay), (bx, by)) in unique_pairs(positions.iter().copied()) {
-            let diffx = ax - bx;
-            let diffy = ay - by;
+/// Describes at which distances from an antenna pair antinodes appear, every ratio is
+/// applied from both antennas of the pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AntinodeModel {
+    /// Only the listed ratios, `{2}` is the rule of part 1.
+    Ratios(Vec<Ratio>),
+    /// Every whole multiple of the antenna distance.
+    Integers,
+    /// Every grid point in line with the antennas, the rule of part 2.
+    Rationals,
+}
+
+impl FromStr for AntinodeModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integers" => Ok(AntinodeModel::Integers),
+            "rationals" => Ok(AntinodeModel::Rationals),
+            _ => s
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map(AntinodeModel::Ratios),
+        }
+    }
+}
 
-            let cx = ax + diffx;
-            let cy = ay + diffy;
+/// Adds the antinodes at the given ratios of the distance between `a` and `b`, measured
+/// from both antennas. Ratios that miss the grid or overflow are skipped.
+fn ratio_antinodes(
+    (ax, ay): (isize, isize),
+    (bx, by): (isize, isize),
+    ratios: &[Ratio],
+    size: (isize, isize),
+    antinode_positions: &mut HashSet<(isize, isize)>,
+) {
+    let diffx = bx - ax;
+    let diffy = by - ay;
+
+    for ratio in ratios {
+        let (Some(offsetx), Some(offsety)) = (
+            diffx.checked_mul(ratio.numerator),
+            diffy.checked_mul(ratio.numerator),
+        ) else {
+            continue;
+        };
+
+        // skip ratios that do not end up on a grid point
+        if offsetx % ratio.denominator != 0 || offsety % ratio.denominator != 0 {
+            continue;
+        }
 
-            if cx >= 0 && cx < width && cy >= 0 && cy < height {
-                antinode_positions.insert((cx, cy));
-            }
+        let offsetx = offsetx / ratio.denominator;
+        let offsety = offsety / ratio.denominator;
 
-            let dx = bx - diffx;
-            let dy = by - diffy;
+        let antinodes = [
+            ax.checked_add(offsetx).zip(ay.checked_add(offsety)),
+            bx.checked_sub(offsetx).zip(by.checked_sub(offsety)),
+        ];
 
-            if dx >= 0 && dx < width && dy >= 0 && dy < height {
-                antinode_positions.insert((dx, dy));
+        for antinode in antinodes.into_iter().flatten() {
+            if in_bounds(antinode, size) {
+                antinode_positions.insert(antinode);
             }
         }
     }
+}
+
+/// Adds every grid point on the line through `a` that is a whole multiple of `step` away.
+fn line_antinodes(
+    (ax, ay): (isize, isize),
+    (stepx, stepy): (isize, isize),
+    size: (isize, isize),
+    antinode_positions: &mut HashSet<(isize, isize)>,
+) {
+    // walking from `a` in both directions also covers `b` and every point in between
+    for sign in [1, -1] {
+        let mut cx = ax;
+        let mut cy = ay;
+
+        while in_bounds((cx, cy), size) {
+            antinode_positions.insert((cx, cy));
+
+            cx += sign * stepx;
+            cy += sign * stepy;
+        }
+    }
+}
 
-    let part_1_solution: usize = antinode_positions.len();
+/// Antinodes of a single frequency according to the given model.
+fn antinodes(
+    antennas: &[(isize, isize)],
+    size: (isize, isize),
+    model: &AntinodeModel,
+) -> HashSet<(isize, isize)> {
+    let mut antinode_positions = HashSet::new();
 
-    let part_1_elapsed = part_1_start.elapsed();
+    for ((ax, ay), (bx, by)) in unique_pairs(antennas.iter().copied()) {
+        let diffx = bx - ax;
+        let diffy = by - ay;
 
-    println!(" Part 1: {}", part_1_solution);
-    println!("   Time: {:?}", part_1_elapsed);
-    println!();
+        match model {
+            AntinodeModel::Ratios(ratios) => {
+                ratio_antinodes((ax, ay), (bx, by), ratios, size, &mut antinode_positions)
+            }
+            AntinodeModel::Integers => {
+                line_antinodes((ax, ay), (diffx, diffy), size, &mut antinode_positions)
+            }
+            // reduce the offset, so every grid point on the line is hit and not only
+            // the ones that are a whole multiple of the antenna distance apart
+            AntinodeModel::Rationals => {
+                let divisor = gcd(diffx, diffy);
+                let step = (diffx / divisor, diffy / divisor);
 
-    antinode_positions.clear();
+                line_antinodes((ax, ay), step, size, &mut antinode_positions)
+            }
+        }
+    }
 
-    let part_2_start = Instant::now();
+    antinode_positions
+}
 
-    for (_, positions) in positions.iter() {
-        for ((ax, ay), (bx, by)) in unique_pairs(positions.iter().copied()) {
-            let diffx = ax - bx;
-            let diffy = ay - by;
+fn antinodes_by_frequency(
+    positions: &Antennas,
+    size: (isize, isize),
+    model: &AntinodeModel,
+) -> Antinodes {
+    positions
+        .iter()
+        .map(|(&frequency, antennas)| (frequency, antinodes(antennas, size, model)))
+        .collect()
+}
 
-            let mut cx = ax + diffx;
-            let mut cy = ay + diffy;
+fn count_unique(antinodes: &Antinodes) -> usize {
+    antinodes.values().flatten().collect::<HashSet<_>>().len()
+}
 
-            while cx >= 0 && cx < width && cy >= 0 && cy < height {
-                antinode_positions.insert((cx, cy));
+/// Renders the map like the puzzle does, antennas are drawn over the antinodes.
+fn render(positions: &Antennas, antinodes: &Antinodes, (width, height): (isize, isize)) -> String {
+    let mut grid = vec![vec!['.'; width as usize]; height as usize];
 
-                cy += diffy;
-                cx += diffx;
-            }
+    for &(x, y) in antinodes.values().flatten() {
+        grid[y as usize][x as usize] = '#';
+    }
 
-            let mut dx = bx - diffx;
-            let mut dy = by - diffy;
+    for (&frequency, antennas) in positions {
+        for &(x, y) in antennas {
+            grid[y as usize][x as usize] = frequency;
+        }
+    }
 
-            while dx >= 0 && dx < width && dy >= 0 && dy < height {
-                antinode_positions.insert((dx, dy));
+    grid.into_iter()
+        .map(|row| row.into_iter().chain(['\n']).collect::<String>())
+        .collect()
+}
 
-                dy -= diffy;
-                dx -= diffx;
-            }
+/// Prints the antenna, pair and antinode count of every frequency, followed by the
+/// positions where antinodes of different frequencies overlap and the rendered map.
+fn report(positions: &Antennas, antinodes: &Antinodes, size: (isize, isize)) {
+    let mut frequencies: Vec<char> = positions.keys().copied().collect();
+    frequencies.sort_unstable();
 
-            antinode_positions.insert((ax, ay));
-            antinode_positions.insert((bx, by));
+    println!("   Frequency  Antennas  Pairs  Antinodes");
+
+    for frequency in &frequencies {
+        let antenna_count = positions[frequency].len();
+
+        println!(
+            "   {:>9}  {:>8}  {:>5}  {:>9}",
+            frequency,
+            antenna_count,
+            antenna_count * antenna_count.saturating_sub(1) / 2,
+            antinodes[frequency].len()
+        );
+    }
+
+    let mut overlaps: HashMap<(isize, isize), Vec<char>> = HashMap::new();
+    for frequency in &frequencies {
+        for &position in &antinodes[frequency] {
+            overlaps.entry(position).or_default().push(*frequency);
         }
     }
 
-    let part_2_solution = antinode_positions.len();
+    let mut overlaps: Vec<_> = overlaps
+        .into_iter()
+        .filter(|(_, frequencies)| frequencies.len() > 1)
+        .collect();
+    overlaps.sort_unstable_by_key(|&((x, y), _)| (y, x));
+
+    println!();
+    println!("   Overlaps: {}", overlaps.len());
+
+    for ((x, y), frequencies) in overlaps {
+        println!(
+            "   ({x},{y}): {}",
+            frequencies.into_iter().collect::<String>()
+        );
+    }
+
+    println!();
+    print!("{}", render(positions, antinodes, size));
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let report_mode = args.iter().any(|arg| arg == "--report");
+
+    let custom_model = args
+        .iter()
+        .position(|arg| arg == "--ratios")
+        .and_then(|index| args.get(index + 1))
+        .map(|ratios| {
+            ratios.parse::<AntinodeModel>().unwrap_or_else(|err| {
+                eprintln!("error: {err}");
+                process::exit(1);
+            })
+        });
+
+    let load_start = Instant::now();
+
+    let (positions, (width, height)) = load();
+
+    let load_elapsed = load_start.elapsed();
+
+    println!("Loading:");
+    println!("   Time: {:?}", load_elapsed);
+    println!();
+
+    let part_1_start = Instant::now();
+
+    let part_1_model = AntinodeModel::Ratios(vec![Ratio {
+        numerator: 2,
+        denominator: 1,
+    }]);
+    let part_1_antinodes = antinodes_by_frequency(&positions, (width, height), &part_1_model);
+    let part_1_solution = count_unique(&part_1_antinodes);
+
+    let part_1_elapsed = part_1_start.elapsed();
+
+    println!(" Part 1: {}", part_1_solution);
+    println!("   Time: {:?}", part_1_elapsed);
+    println!();
+
+    let part_2_start = Instant::now();
+
+    let part_2_antinodes =
+        antinodes_by_frequency(&positions, (width, height), &AntinodeModel::Rationals);
+    let part_2_solution = count_unique(&part_2_antinodes);
 
     let part_2_elapsed = part_2_start.elapsed();
 
@@ -144,4 +364,115 @@ fn main() {
         (load_elapsed) + (part_1_elapsed) + (part_2_elapsed)
     );
     println!();
+
+    let custom_antinodes = custom_model.map(|model| {
+        let custom_start = Instant::now();
+
+        let custom_antinodes = antinodes_by_frequency(&positions, (width, height), &model);
+
+        let custom_elapsed = custom_start.elapsed();
+
+        println!(" Custom: {}", count_unique(&custom_antinodes));
+        println!("   Time: {:?}", custom_elapsed);
+        println!();
+
+        custom_antinodes
+    });
+
+    if report_mode {
+        println!(" Part 1 Report:");
+        report(&positions, &part_1_antinodes, (width, height));
+        println!();
+
+        println!(" Part 2 Report:");
+        report(&positions, &part_2_antinodes, (width, height));
+
+        if let Some(custom_antinodes) = &custom_antinodes {
+            println!();
+            println!(" Custom Report:");
+            report(&positions, custom_antinodes, (width, height));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: (isize, isize) = (10, 10);
+
+    /// Two antennas whose offset `(2, 4)` is not primitive, `(1, 2)` lies between them.
+    const ANTENNAS: [(isize, isize); 2] = [(2, 1), (4, 5)];
+
+    fn sorted(antinodes: HashSet<(isize, isize)>) -> Vec<(isize, isize)> {
+        let mut antinodes: Vec<_> = antinodes.into_iter().collect();
+        antinodes.sort_unstable();
+        antinodes
+    }
+
+    #[test]
+    fn rationals_hit_points_between_the_antennas() {
+        assert_eq!(
+            sorted(antinodes(&ANTENNAS, SIZE, &AntinodeModel::Rationals)),
+            [(1, -1), (2, 1), (3, 3), (4, 5), (5, 7), (6, 9)]
+                .into_iter()
+                .filter(|&antinode| in_bounds(antinode, SIZE))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn integers_skip_points_between_the_antennas() {
+        assert_eq!(
+            sorted(antinodes(&ANTENNAS, SIZE, &AntinodeModel::Integers)),
+            [(2, 1), (4, 5), (6, 9)]
+        );
+    }
+
+    #[test]
+    fn ratios_only_land_on_grid_points() {
+        let model: AntinodeModel = "1/2,1/3,2".parse().unwrap();
+
+        // a third of the offset is not a grid point, and of the two points at twice
+        // the distance only `(6, 9)` is in bounds
+        assert_eq!(sorted(antinodes(&ANTENNAS, SIZE, &model)), [(3, 3), (6, 9)]);
+    }
+
+    #[test]
+    fn overflowing_ratios_are_skipped() {
+        let model = AntinodeModel::Ratios(vec![
+            Ratio {
+                numerator: isize::MAX,
+                denominator: 1,
+            },
+            Ratio {
+                numerator: 2,
+                denominator: 1,
+            },
+        ]);
+
+        assert_eq!(sorted(antinodes(&ANTENNAS, SIZE, &model)), [(6, 9)]);
+    }
+
+    #[test]
+    fn part_1_is_unchanged() {
+        let (positions, size) = load();
+        let model = AntinodeModel::Ratios(vec![Ratio {
+            numerator: 2,
+            denominator: 1,
+        }]);
+
+        assert_eq!(
+            count_unique(&antinodes_by_frequency(&positions, size, &model)),
+            392
+        );
+        assert_eq!(
+            count_unique(&antinodes_by_frequency(
+                &positions,
+                size,
+                &AntinodeModel::Rationals
+            )),
+            1235
+        );
+    }
 }
@@ -1,4 +1,9 @@
-use std::{iter::repeat_n, time::Instant, usize};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    env, fmt, fs, process,
+    time::Instant,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Cell {
@@ -6,189 +11,518 @@ enum Cell {
     Empty,
 }
 
-fn load() -> Vec<Cell> {
-    let input = include_str!("input.txt");
-
-    let mut id_counter = 0;
+/// A contiguous range of blocks that either belong to the same file or are all empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Run {
+    pub cell: Cell,
+    pub start: usize,
+    pub length: usize,
+}
 
-    input
-        .trim()
-        .chars()
-        .map(|ch| ch.to_digit(10).expect("failed to convert char") as usize)
-        .enumerate()
-        .flat_map(|(idx, length)| {
-            let id = id_counter;
+impl Run {
+    pub fn end(&self) -> usize {
+        self.start + self.length
+    }
 
-            if idx % 2 == 0 {
-                id_counter += 1;
-                repeat_n(Cell::File(id), length)
-            } else {
-                repeat_n(Cell::Empty, length)
+    /// The sum of `position * id` over all blocks of the run, `0` for empty runs.
+    pub fn checksum(&self) -> usize {
+        match self.cell {
+            // sum of the positions `start..start + length`, times the file id
+            Cell::File(id) => {
+                id * (self.length * self.start + self.length * self.length.saturating_sub(1) / 2)
             }
-        })
-        .collect()
+            Cell::Empty => 0,
+        }
+    }
 }
 
-fn main() {
-    let load_start = Instant::now();
+/// A disk stored as an ordered list of runs, so memory and time scale with the number
+/// of runs instead of the number of blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DiskMap {
+    runs: Vec<Run>,
+    length: usize,
+}
 
-    let disk_map = load();
+/// Appends a run, merging it into the previous one when both hold the same cell, so
+/// neighbouring gaps and pieces of the same file always form a single run.
+fn push_run(runs: &mut Vec<Run>, run: Run) {
+    if run.length == 0 {
+        return;
+    }
 
-    let load_elapsed = load_start.elapsed();
+    match runs.last_mut() {
+        Some(last) if last.cell == run.cell && last.end() == run.start => {
+            last.length += run.length;
+        }
+        _ => runs.push(run),
+    }
+}
 
-    println!("Loading:");
-    println!("   Time: {:?}", load_elapsed);
-    println!();
+impl DiskMap {
+    pub fn parse(input: &str) -> Self {
+        let mut runs = Vec::new();
+        let mut start = 0;
 
-    let part_1_start = Instant::now();
+        for (idx, ch) in input.trim().chars().enumerate() {
+            let length = ch.to_digit(10).expect("failed to convert char") as usize;
 
-    let mut iterations: usize = 0;
-    let mut last_empty_index = 0;
+            let cell = if idx % 2 == 0 {
+                Cell::File(idx / 2)
+            } else {
+                Cell::Empty
+            };
+
+            push_run(
+                &mut runs,
+                Run {
+                    cell,
+                    start,
+                    length,
+                },
+            );
+
+            start += length;
+        }
+
+        Self {
+            runs,
+            length: start,
+        }
+    }
 
-    let mut part_1_disk_map = disk_map.clone();
+    /// Builds a disk of the given length from file runs, the gaps between them are
+    /// filled with empty runs.
+    pub fn from_files(mut files: Vec<Run>, length: usize) -> Self {
+        files.sort_unstable_by_key(|run| run.start);
+
+        let mut runs = Vec::with_capacity(files.len() * 2);
+        let mut position = 0;
+
+        for file in files {
+            if file.start > position {
+                push_run(
+                    &mut runs,
+                    Run {
+                        cell: Cell::Empty,
+                        start: position,
+                        length: file.start - position,
+                    },
+                );
+            }
 
-    while let Some(cell) = part_1_disk_map.pop() {
-        iterations += 1;
+            push_run(&mut runs, file);
 
-        if matches!(cell, Cell::Empty) {
-            continue;
+            position = file.end();
         }
 
-        match part_1_disk_map
-            .iter_mut()
-            .skip(last_empty_index)
-            .enumerate()
-            .find(|(_, cell)| matches!(cell, Cell::Empty))
-        {
-            Some((idx, empty_cell)) => {
-                *empty_cell = cell;
-                last_empty_index += idx + 1;
-            }
-            None => {
-                part_1_disk_map.push(cell);
-                break;
+        if length > position {
+            push_run(
+                &mut runs,
+                Run {
+                    cell: Cell::Empty,
+                    start: position,
+                    length: length - position,
+                },
+            );
+        }
+
+        Self { runs, length }
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &Run> {
+        self.runs
+            .iter()
+            .filter(|run| matches!(run.cell, Cell::File(_)))
+    }
+
+    pub fn checksum(&self) -> usize {
+        self.runs.iter().map(Run::checksum).sum()
+    }
+
+    pub fn fragmentation(&self) -> Fragmentation {
+        let mut runs_per_file: HashMap<usize, usize> = HashMap::new();
+        for run in self.files() {
+            if let Cell::File(id) = run.cell {
+                *runs_per_file.entry(id).or_default() += 1;
             }
         }
+
+        // empty runs after the last file do not count as gaps
+        let files_end = self.files().map(Run::end).max().unwrap_or(0);
+        let gaps: Vec<&Run> = self
+            .runs
+            .iter()
+            .filter(|run| run.cell == Cell::Empty && run.start < files_end)
+            .collect();
+
+        Fragmentation {
+            file_runs: runs_per_file.values().sum(),
+            fragmented_files: runs_per_file.values().filter(|&&runs| runs > 1).count(),
+            gaps: gaps.len(),
+            gap_blocks: gaps.iter().map(|run| run.length).sum(),
+        }
     }
+}
 
-    let part_1_solution: usize = part_1_disk_map
-        .iter()
-        .enumerate()
-        .map(|(idx, cell)| match cell {
-            &Cell::File(id) => id * idx,
-            Cell::Empty => 0,
-        })
-        .sum();
+/// Renders the disk like the puzzle does, e.g. `00...111...2...333.44`. Only the last
+/// digit of file ids above 9 is shown.
+impl fmt::Display for DiskMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for run in &self.runs {
+            let glyph = match run.cell {
+                Cell::File(id) => char::from_digit((id % 10) as u32, 10).unwrap(),
+                Cell::Empty => '.',
+            };
+
+            for _ in 0..run.length {
+                write!(f, "{glyph}")?;
+            }
+        }
 
-    let part_1_elapsed = part_1_start.elapsed();
+        Ok(())
+    }
+}
 
-    println!(" Part 1: {} ({})", part_1_solution, iterations);
-    println!("   Time: {:?}", part_1_elapsed);
-    println!();
+/// Describes how scattered the files on a disk are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fragmentation {
+    pub file_runs: usize,
+    /// Files that are split into more than one run.
+    pub fragmented_files: usize,
+    /// Empty runs in front of the last file block.
+    pub gaps: usize,
+    pub gap_blocks: usize,
+}
 
-    let part_2_start = Instant::now();
+/// The result of compacting a disk, a move relocates one contiguous piece of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Compaction {
+    pub disk_map: DiskMap,
+    pub moves: usize,
+}
 
-    let mut part_2_disk_map = disk_map;
+/// Called with the current state of the disk after every move.
+type Trace<'a> = Option<&'a mut dyn FnMut(&DiskMap)>;
 
-    let mut empty_spaces = Vec::new();
-    let mut file_spaces = Vec::new();
+trait CompactionStrategy {
+    fn name(&self) -> &'static str;
 
-    let mut empty_start = None;
-    let mut empty_length: usize = 0;
+    fn compact(&self, disk_map: &DiskMap, trace: Trace) -> Compaction;
+}
 
-    let mut file_start = None;
-    let mut file_id = 0;
-    let mut file_length: usize = 0;
+const STRATEGIES: [&dyn CompactionStrategy; 5] =
+    [&Fragment, &Fit::First, &Fit::Best, &Fit::Worst, &Defragment];
 
-    for (i, cell) in part_2_disk_map.iter().enumerate() {
-        match cell {
-            Cell::Empty => {
-                if let Some(file_start) = file_start.take() {
-                    file_spaces.push((file_start, file_length));
-                }
+/// Moves file blocks one at a time from the end of the disk into the leftmost empty
+/// block, this is the strategy of part 1.
+struct Fragment;
 
-                // Start a new empty block
-                if empty_start.is_none() {
-                    empty_start = Some(i);
-                    empty_length = 0;
-                }
+impl CompactionStrategy for Fragment {
+    fn name(&self) -> &'static str {
+        "fragment"
+    }
 
-                empty_length += 1;
+    /// Uses one pointer walking forward over the empty runs and one walking backwards
+    /// over the file runs, every piece filling an empty run counts as one move.
+    fn compact(&self, disk_map: &DiskMap, mut trace: Trace) -> Compaction {
+        let runs = &disk_map.runs;
+
+        let mut files = Vec::with_capacity(runs.len());
+        let mut moves = 0;
+
+        let mut left = 0;
+        let Some(mut right) = runs.iter().rposition(|run| run.cell != Cell::Empty) else {
+            return Compaction {
+                disk_map: disk_map.clone(),
+                moves,
+            };
+        };
+        let mut remaining = runs[right].length;
+
+        while left < right {
+            let run = runs[left];
+            left += 1;
+
+            if run.cell != Cell::Empty {
+                files.push(run);
+                continue;
             }
-            &Cell::File(id) => {
-                if let Some(empty_start) = empty_start.take() {
-                    empty_spaces.push((empty_start, empty_length));
-                }
 
-                if id != file_id {
-                    if let Some(file_start) = file_start.take() {
-                        file_spaces.push((file_start, file_length));
-                        file_length = 0;
+            let mut free_start = run.start;
+            let mut free_length = run.length;
+
+            while free_length > 0 && left <= right {
+                let moved = free_length.min(remaining);
+
+                files.push(Run {
+                    cell: runs[right].cell,
+                    start: free_start,
+                    length: moved,
+                });
+                moves += 1;
+
+                free_start += moved;
+                free_length -= moved;
+                remaining -= moved;
+
+                if remaining == 0 {
+                    // continue with the next file run to the left
+                    right -= 1;
+                    while right >= left && runs[right].cell == Cell::Empty {
+                        right -= 1;
                     }
+                    remaining = runs[right].length;
                 }
 
-                // Start a new file block
-                if file_start.is_none() {
-                    file_start = Some(i);
-                    file_length = 0;
-                    file_id = id;
-                }
+                if let Some(trace) = trace.as_mut() {
+                    // the files that have not been reached yet are still in their place
+                    let mut state = files.clone();
+
+                    if left <= right {
+                        state.extend(
+                            runs[left..right]
+                                .iter()
+                                .filter(|run| run.cell != Cell::Empty),
+                        );
+                        state.push(Run {
+                            length: remaining,
+                            ..runs[right]
+                        });
+                    }
 
-                file_length += 1;
+                    trace(&DiskMap::from_files(state, disk_map.length));
+                }
             }
         }
+
+        // whatever is left of the file that was being moved stays at its front
+        if left == right && remaining > 0 {
+            files.push(Run {
+                length: remaining,
+                ..runs[right]
+            });
+        }
+
+        Compaction {
+            disk_map: DiskMap::from_files(files, disk_map.length),
+            moves,
+        }
     }
+}
+
+/// Which empty run a whole file is moved into, only runs left of the file are considered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fit {
+    /// The leftmost empty run that is large enough, this is the strategy of part 2.
+    First,
+    /// The smallest empty run that is large enough.
+    Best,
+    /// The largest empty run.
+    Worst,
+}
 
-    if let Some(file_start) = file_start.take() {
-        file_spaces.push((file_start, file_length));
+impl CompactionStrategy for Fit {
+    fn name(&self) -> &'static str {
+        match self {
+            Fit::First => "first-fit",
+            Fit::Best => "best-fit",
+            Fit::Worst => "worst-fit",
+        }
     }
 
-    if let Some(empty_start) = empty_start.take() {
-        empty_spaces.push((empty_start, empty_length));
+    /// Moves whole files, starting with the highest id, into an empty run that is
+    /// large enough to hold them.
+    fn compact(&self, disk_map: &DiskMap, mut trace: Trace) -> Compaction {
+        let mut files: Vec<Run> = disk_map.files().copied().collect();
+        let mut free_lists =
+            FreeLists::new(disk_map.runs.iter().filter(|run| run.cell == Cell::Empty));
+        let mut moves = 0;
+
+        for index in (0..files.len()).rev() {
+            let file = &mut files[index];
+
+            if let Some(empty_start) = free_lists.take(file.length, file.start, *self) {
+                file.start = empty_start;
+                moves += 1;
+
+                if let Some(trace) = trace.as_mut() {
+                    trace(&DiskMap::from_files(files.clone(), disk_map.length));
+                }
+            }
+        }
+
+        Compaction {
+            disk_map: DiskMap::from_files(files, disk_map.length),
+            moves,
+        }
     }
+}
 
-    while let Some((file_index, file_length)) = file_spaces.pop() {
-        let result = empty_spaces
-            .iter()
-            .copied()
-            .position(|(_, empty_length)| empty_length >= file_length);
+/// Packs all file runs to the front of the disk in their original order, which leaves
+/// no gaps between them. Files that are split into several runs stay split, every file
+/// of a freshly parsed disk is a single run though.
+struct Defragment;
 
-        if result.is_none() {
-            continue;
+impl CompactionStrategy for Defragment {
+    fn name(&self) -> &'static str {
+        "defragment"
+    }
+
+    fn compact(&self, disk_map: &DiskMap, mut trace: Trace) -> Compaction {
+        let mut files: Vec<Run> = Vec::new();
+        let mut moves = 0;
+        let mut position = 0;
+
+        for (index, run) in disk_map.files().enumerate() {
+            files.push(Run {
+                start: position,
+                ..*run
+            });
+            position += run.length;
+
+            // the run is already in place
+            if run.start == files[index].start {
+                continue;
+            }
+
+            moves += 1;
+
+            if let Some(trace) = trace.as_mut() {
+                let mut state = files.clone();
+                state.extend(disk_map.files().skip(index + 1));
+
+                trace(&DiskMap::from_files(state, disk_map.length));
+            }
         }
 
-        let idx = result.unwrap();
-        let (empty_index, empty_length) = empty_spaces[idx];
+        Compaction {
+            disk_map: DiskMap::from_files(files, disk_map.length),
+            moves,
+        }
+    }
+}
+
+/// Keeps one min-heap of start offsets for every length of empty run, so the leftmost
+/// empty run of at least a given length is found without scanning all of them.
+#[derive(Debug, Clone, Default)]
+struct FreeLists {
+    heaps: Vec<BinaryHeap<Reverse<usize>>>,
+}
 
-        if empty_index > file_index {
-            continue;
+impl FreeLists {
+    pub fn new<'a>(empty_runs: impl Iterator<Item = &'a Run>) -> Self {
+        let mut free_lists = Self::default();
+
+        for run in empty_runs {
+            free_lists.insert(run.start, run.length);
         }
 
-        let left_over_empty_space = empty_length - file_length;
+        free_lists
+    }
 
-        for i in 0..file_length {
-            part_2_disk_map.swap(file_index + i, empty_index + i);
+    pub fn insert(&mut self, start: usize, length: usize) {
+        if length == 0 {
+            return;
         }
 
-        if left_over_empty_space > 0 {
-            empty_spaces[idx] = (empty_index + file_length, left_over_empty_space);
-        } else {
-            empty_spaces[idx] = (usize::MAX, 0);
+        if self.heaps.len() <= length {
+            self.heaps.resize_with(length + 1, BinaryHeap::new);
         }
+
+        self.heaps[length].push(Reverse(start));
     }
 
-    let part_2_solution: usize = part_2_disk_map
-        .iter()
-        .enumerate()
-        .map(|(idx, cell)| match cell {
-            &Cell::File(id) => id * idx,
-            Cell::Empty => 0,
-        })
-        .sum();
+    /// Removes an empty run that holds at least `length` blocks and starts before
+    /// `before`, the part that is not needed is put back as a shorter run.
+    pub fn take(&mut self, length: usize, before: usize, fit: Fit) -> Option<usize> {
+        // only the leftmost run of every length is a candidate, ordered by length
+        let mut candidates = self
+            .heaps
+            .iter()
+            .enumerate()
+            .skip(length)
+            .filter_map(|(run_length, heap)| heap.peek().map(|&Reverse(start)| (run_length, start)))
+            .filter(|&(_, start)| start < before);
+
+        let (run_length, start) = match fit {
+            Fit::First => candidates.min_by_key(|&(_, start)| start),
+            Fit::Best => candidates.next(),
+            Fit::Worst => candidates.next_back(),
+        }?;
+
+        self.heaps[run_length].pop();
+        self.insert(start + length, run_length - length);
+
+        Some(start)
+    }
+}
+
+/// The largest disk that is traced block by block, larger ones would flood the terminal.
+const TRACE_LIMIT: usize = 1000;
+
+fn load() -> DiskMap {
+    DiskMap::parse(include_str!("input.txt"))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let compare_strategies = args.iter().any(|arg| arg == "--strategies");
+    let trace_mode = args.iter().any(|arg| arg == "--trace");
+
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|index| args.get(index + 1))
+    };
+
+    let strategy_name = option("--strategy").map_or("first-fit", String::as_str);
+    let output_path = option("--output");
+
+    let strategy = STRATEGIES
+        .into_iter()
+        .find(|strategy| strategy.name() == strategy_name)
+        .unwrap_or_else(|| {
+            eprintln!("error: unknown compaction strategy {strategy_name:?}");
+            process::exit(1);
+        });
+
+    let load_start = Instant::now();
+
+    let disk_map = load();
+
+    let load_elapsed = load_start.elapsed();
+
+    println!("Loading:");
+    println!("   Time: {:?}", load_elapsed);
+    println!();
+
+    let part_1_start = Instant::now();
+
+    let part_1_compaction = Fragment.compact(&disk_map, None);
+    let part_1_solution = part_1_compaction.disk_map.checksum();
+
+    let part_1_elapsed = part_1_start.elapsed();
+
+    println!(
+        " Part 1: {} ({} moves)",
+        part_1_solution, part_1_compaction.moves
+    );
+    println!("   Time: {:?}", part_1_elapsed);
+    println!();
+
+    let part_2_start = Instant::now();
+
+    let part_2_compaction = Fit::First.compact(&disk_map, None);
+    let part_2_solution = part_2_compaction.disk_map.checksum();
 
     let part_2_elapsed = part_2_start.elapsed();
 
-    println!(" Part 2: {}", part_2_solution);
+    println!(
+        " Part 2: {} ({} moves)",
+        part_2_solution, part_2_compaction.moves
+    );
     println!("   Time: {:?}", part_2_elapsed);
     println!();
 
@@ -198,4 +532,98 @@ fn main() {
         (load_elapsed) + (part_1_elapsed) + (part_2_elapsed)
     );
     println!();
+
+    if compare_strategies {
+        println!(
+            "   {:<10}  {:>16}  {:>6}  {:>9}  {:>10}  {:>5}  {:>10}",
+            "Strategy", "Checksum", "Moves", "File Runs", "Fragmented", "Gaps", "Gap Blocks"
+        );
+
+        for strategy in STRATEGIES {
+            let compaction = strategy.compact(&disk_map, None);
+            let fragmentation = compaction.disk_map.fragmentation();
+
+            println!(
+                "   {:<10}  {:>16}  {:>6}  {:>9}  {:>10}  {:>5}  {:>10}",
+                strategy.name(),
+                compaction.disk_map.checksum(),
+                compaction.moves,
+                fragmentation.file_runs,
+                fragmentation.fragmented_files,
+                fragmentation.gaps,
+                fragmentation.gap_blocks
+            );
+        }
+
+        println!();
+    }
+
+    if trace_mode {
+        if disk_map.length > TRACE_LIMIT {
+            eprintln!(
+                "error: tracing is limited to disks of up to {TRACE_LIMIT} blocks, this one has {}",
+                disk_map.length
+            );
+            process::exit(1);
+        }
+
+        println!("   Trace: {}", strategy.name());
+        println!("          {}", disk_map);
+
+        strategy.compact(
+            &disk_map,
+            Some(&mut |state: &DiskMap| println!("          {}", state)),
+        );
+
+        println!();
+    }
+
+    if let Some(output_path) = output_path {
+        let compaction = strategy.compact(&disk_map, None);
+
+        if let Err(err) = fs::write(output_path, format!("{}\n", compaction.disk_map)) {
+            eprintln!("error: failed to write layout to {output_path:?}: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksums(input: &str) -> (usize, usize) {
+        let disk_map = DiskMap::parse(input);
+
+        (
+            Fragment.compact(&disk_map, None).disk_map.checksum(),
+            Fit::First.compact(&disk_map, None).disk_map.checksum(),
+        )
+    }
+
+    #[test]
+    fn example() {
+        assert_eq!(checksums("2333133121414131402"), (1928, 2858));
+    }
+
+    #[test]
+    fn zero_length_files_leave_a_single_gap() {
+        let disk_map = DiskMap::parse("270316523");
+
+        assert!(disk_map
+            .runs
+            .windows(2)
+            .all(|pair| pair[0].cell != pair[1].cell));
+        assert_eq!(disk_map.to_string(), "00..........2......33333..444");
+        assert_eq!(checksums("270316523").1, 161);
+    }
+
+    #[test]
+    fn from_files_merges_neighbouring_runs() {
+        let disk_map = DiskMap::parse("1010101");
+        let rebuilt = DiskMap::from_files(disk_map.files().copied().collect(), disk_map.length);
+
+        assert_eq!(rebuilt.runs.len(), disk_map.runs.len());
+        assert_eq!(rebuilt.to_string(), "0123");
+    }
 }
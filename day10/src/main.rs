@@ -1,15 +1,21 @@
-use std::{collections::HashSet, time::Instant};
+use std::{env, process, str::FromStr, time::Instant};
 
-fn load() -> (Vec<Vec<usize>>, usize) {
-    let input = include_str!("input.txt");
+/// The elevation of a tile, `None` for tiles that cannot be walked on.
+type Tile = Option<usize>;
 
+fn load() -> (Vec<Vec<Tile>>, usize) {
+    parse(include_str!("input.txt"))
+}
+
+/// Parses the map, every tile that is not a digit is impassable.
+fn parse(input: &str) -> (Vec<Vec<Tile>>, usize) {
     let map: Vec<Vec<_>> = input
         .lines()
         .map(str::trim)
         .filter(|line| !line.is_empty())
         .map(|line| {
             line.chars()
-                .map(|ch| ch.to_digit(10).unwrap() as usize)
+                .map(|ch| ch.to_digit(10).map(|elevation| elevation as usize))
                 .collect()
         })
         .collect();
@@ -21,58 +27,368 @@ fn load() -> (Vec<Vec<usize>>, usize) {
 
 const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
 
-fn score(map: &Vec<Vec<usize>>, x: usize, y: usize, size: usize, allow_revisit: bool) -> usize {
-    if map[y][x] != 0 {
-        return 0;
+/// The allowed change in elevation of a single step, both ends inclusive. Trails either
+/// always climb or always descend, so they can never walk in circles.
+#[derive(Clone, Copy)]
+struct StepRule {
+    min_climb: isize,
+    max_climb: isize,
+}
+
+impl StepRule {
+    fn allows(&self, from: usize, to: usize) -> bool {
+        (self.min_climb..=self.max_climb).contains(&(to as isize - from as isize))
     }
+}
 
-    let mut answer = 0;
+/// Parses either a single climb like `1` or a range like `1..2` or `-2..-1`.
+impl FromStr for StepRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min_climb, max_climb) = s.split_once("..").unwrap_or((s, s));
+
+        let min_climb: isize = min_climb
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid step rule {s:?}"))?;
+        let max_climb: isize = max_climb
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid step rule {s:?}"))?;
+
+        if min_climb > max_climb {
+            return Err(format!(
+                "invalid step rule {s:?}, the range must not be empty"
+            ));
+        }
 
-    let mut stack = vec![(x, y)];
-    let mut visited = HashSet::new();
+        // a range containing zero, or both climbing and descending steps, would let
+        // trails go back and forth forever
+        if min_climb <= 0 && max_climb >= 0 {
+            return Err(format!(
+                "invalid step rule {s:?}, steps have to either always climb or always descend"
+            ));
+        }
 
-    // Depth first search
-    while let Some((cur_x, cur_y)) = stack.pop() {
-        let cur_elev = map[cur_y][cur_x];
+        Ok(StepRule {
+            min_climb,
+            max_climb,
+        })
+    }
+}
 
-        if !allow_revisit && visited.contains(&(cur_x, cur_y)) {
-            continue;
-        }
+/// Where trails start and end and how they may get there.
+#[derive(Clone, Copy)]
+struct TrailRules {
+    step: StepRule,
+    trailhead: usize,
+    peak: usize,
+}
 
-        visited.insert((cur_x, cur_y));
+const PUZZLE_RULES: TrailRules = TrailRules {
+    step: StepRule {
+        min_climb: 1,
+        max_climb: 1,
+    },
+    trailhead: 0,
+    peak: 9,
+};
+
+/// A set of peaks, one bit per peak index.
+#[derive(Clone)]
+struct PeakSet(Vec<u64>);
+
+impl PeakSet {
+    fn new(peak_count: usize) -> Self {
+        Self(vec![0; peak_count.div_ceil(64)])
+    }
 
-        if cur_elev == 9 {
-            answer += 1;
-            continue;
+    fn insert(&mut self, peak: usize) {
+        self.0[peak / 64] |= 1 << (peak % 64);
+    }
+
+    fn union_with(&mut self, other: &PeakSet) {
+        for (word, other_word) in self.0.iter_mut().zip(&other.0) {
+            *word |= other_word;
         }
+    }
 
-        for (step_x, step_y) in DIRECTIONS {
-            let next_x = cur_x as isize + step_x;
-            let next_y = cur_y as isize + step_y;
+    fn len(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
 
-            let is_in_grid =
-                (0 <= next_x && next_x < size as isize) && (0 <= next_y && next_y < size as isize);
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| index * 64 + bit)
+        })
+    }
+}
+
+/// What can be reached from a cell by following the step rule.
+#[derive(Clone)]
+struct Reach {
+    /// The peaks at the end of any trail starting here, its size is the score.
+    peaks: PeakSet,
+    /// The number of distinct trails starting here.
+    rating: usize,
+}
+
+fn neighbours(x: usize, y: usize, size: usize) -> impl Iterator<Item = (usize, usize)> {
+    DIRECTIONS.into_iter().filter_map(move |(step_x, step_y)| {
+        let next_x = x.checked_add_signed(step_x)?;
+        let next_y = y.checked_add_signed(step_y)?;
+
+        (next_x < size && next_y < size).then_some((next_x, next_y))
+    })
+}
+
+type Position = (usize, usize);
+
+/// The cells a hiker standing at `(x, y)` may step to next, a trail ends at its peak.
+fn steps<'a>(
+    map: &'a [Vec<Tile>],
+    size: usize,
+    rules: &'a TrailRules,
+    (x, y): Position,
+) -> impl Iterator<Item = Position> + 'a {
+    let elevation = map[y][x].filter(|&elevation| elevation != rules.peak);
+
+    neighbours(x, y, size).filter(
+        move |&(next_x, next_y)| match (elevation, map[next_y][next_x]) {
+            (Some(from), Some(to)) => rules.step.allows(from, to),
+            _ => false,
+        },
+    )
+}
+
+/// The reach of every cell, along with the peaks the bits in the peak sets refer to.
+struct Sweep {
+    rules: TrailRules,
+    reach: Vec<Vec<Reach>>,
+    peaks: Vec<Position>,
+}
 
-            if !is_in_grid {
+/// Computes the reach of every cell in a single sweep from the peaks back to the
+/// trailheads. A cell is swept once all cells it can step to are, so every cell only
+/// has to look at its direct neighbours.
+fn sweep(map: &[Vec<Tile>], size: usize, rules: TrailRules) -> Sweep {
+    let mut pending = vec![vec![0; size]; size];
+    let mut predecessors = vec![vec![Vec::new(); size]; size];
+    let mut ready = Vec::new();
+    let mut peaks = Vec::new();
+
+    for (y, row) in map.iter().enumerate() {
+        for (x, &tile) in row.iter().enumerate() {
+            if tile.is_none() {
                 continue;
             }
 
-            let next_x = next_x as usize;
-            let next_y = next_y as usize;
-            let next_elev = map[next_y][next_x];
+            if tile == Some(rules.peak) {
+                peaks.push((x, y));
+            }
 
-            if next_elev != cur_elev + 1 {
-                continue;
+            for (next_x, next_y) in steps(map, size, &rules, (x, y)) {
+                pending[y][x] += 1;
+                predecessors[next_y][next_x].push((x, y));
             }
 
-            stack.push((next_x, next_y));
+            if pending[y][x] == 0 {
+                ready.push((x, y));
+            }
         }
     }
 
-    answer
+    let mut reach = vec![
+        vec![
+            Reach {
+                peaks: PeakSet::new(peaks.len()),
+                rating: 0,
+            };
+            size
+        ];
+        size
+    ];
+
+    for (peak, &(x, y)) in peaks.iter().enumerate() {
+        reach[y][x].peaks.insert(peak);
+        reach[y][x].rating = 1;
+    }
+
+    // the step rule never allows cycles, so every cell eventually becomes ready
+    while let Some((x, y)) = ready.pop() {
+        let cell = reach[y][x].clone();
+
+        for &(previous_x, previous_y) in &predecessors[y][x] {
+            let previous = &mut reach[previous_y][previous_x];
+            previous.peaks.union_with(&cell.peaks);
+            previous.rating += cell.rating;
+
+            pending[previous_y][previous_x] -= 1;
+
+            if pending[previous_y][previous_x] == 0 {
+                ready.push((previous_x, previous_y));
+            }
+        }
+    }
+
+    Sweep {
+        rules,
+        reach,
+        peaks,
+    }
+}
+
+fn trailheads(map: &[Vec<Tile>], trailhead: usize) -> impl Iterator<Item = Position> + '_ {
+    map.iter().enumerate().flat_map(move |(y, row)| {
+        row.iter()
+            .enumerate()
+            .filter(move |&(_, &tile)| tile == Some(trailhead))
+            .map(move |(x, _)| (x, y))
+    })
+}
+
+struct Trailhead {
+    position: Position,
+    score: usize,
+    rating: usize,
+    peaks: Vec<Position>,
+    /// The first few distinct hiking trails, each listed from trailhead to peak.
+    trails: Vec<Vec<Position>>,
+}
+
+/// Describes every trailhead on the map, listing up to `trail_limit` of its trails.
+fn report(map: &[Vec<Tile>], size: usize, sweep: &Sweep, trail_limit: usize) -> Vec<Trailhead> {
+    trailheads(map, sweep.rules.trailhead)
+        .map(|(x, y)| {
+            let reach = &sweep.reach[y][x];
+
+            Trailhead {
+                position: (x, y),
+                score: reach.peaks.len(),
+                rating: reach.rating,
+                peaks: reach.peaks.iter().map(|peak| sweep.peaks[peak]).collect(),
+                trails: trails(map, size, sweep, (x, y), trail_limit),
+            }
+        })
+        .collect()
+}
+
+/// Enumerates up to `limit` distinct trails starting at `start`, the sweep is used to
+/// skip every neighbour that does not lead to a peak.
+fn trails(
+    map: &[Vec<Tile>],
+    size: usize,
+    sweep: &Sweep,
+    start: Position,
+    limit: usize,
+) -> Vec<Vec<Position>> {
+    let mut trails = Vec::new();
+    let mut stack = vec![vec![start]];
+
+    while let Some(trail) = stack.pop() {
+        if trails.len() == limit {
+            break;
+        }
+
+        let (x, y) = *trail.last().unwrap();
+        if map[y][x] == Some(sweep.rules.peak) {
+            trails.push(trail);
+            continue;
+        }
+
+        // pushed in reverse, so the trails come out in the order of `DIRECTIONS`
+        let next_steps: Vec<_> = steps(map, size, &sweep.rules, (x, y))
+            .filter(|&(next_x, next_y)| sweep.reach[next_y][next_x].rating > 0)
+            .collect();
+
+        for next in next_steps.into_iter().rev() {
+            let mut next_trail = trail.clone();
+            next_trail.push(next);
+            stack.push(next_trail);
+        }
+    }
+
+    trails
+}
+
+/// Renders the map with only the cells on the given trails, every other cell is a `.`.
+fn render(map: &[Vec<Tile>], trails: &[Vec<Position>]) -> String {
+    let mut grid: Vec<Vec<char>> = map.iter().map(|row| vec!['.'; row.len()]).collect();
+
+    for &(x, y) in trails.iter().flatten() {
+        grid[y][x] = char::from_digit(map[y][x].unwrap() as u32, 10).unwrap();
+    }
+
+    grid.into_iter()
+        .map(|row| row.into_iter().collect::<String>() + "\n")
+        .collect()
+}
+
+fn parse_position(text: &str) -> Result<Position, String> {
+    let invalid = || format!("invalid position {text:?}, expected x,y");
+
+    let (x, y) = text.split_once(',').ok_or_else(invalid)?;
+
+    Ok((
+        x.trim().parse().map_err(|_| invalid())?,
+        y.trim().parse().map_err(|_| invalid())?,
+    ))
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|index| args.get(index + 1))
+    };
+
+    let show_report = args.iter().any(|arg| arg == "--report");
+    let trail_limit: usize = option("--trails").map_or(0, |limit| {
+        limit.parse().unwrap_or_else(|_| {
+            eprintln!("error: invalid trail limit {limit:?}");
+            process::exit(1);
+        })
+    });
+    let render_position = option("--render").map(|position| {
+        parse_position(position).unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            process::exit(1);
+        })
+    });
+
+    let step = option("--step").map(|step| {
+        step.parse().unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            process::exit(1);
+        })
+    });
+    let height = |name: &str| {
+        option(name).map(|height| {
+            height
+                .parse::<usize>()
+                .ok()
+                .filter(|&height| height <= 9)
+                .unwrap_or_else(|| {
+                    eprintln!("error: {name} should be a height between 0 and 9");
+                    process::exit(1);
+                })
+        })
+    };
+    let trailhead = height("--trailhead");
+    let peak = height("--peak");
+
+    let custom_rules =
+        (step.is_some() || trailhead.is_some() || peak.is_some()).then(|| TrailRules {
+            step: step.unwrap_or(PUZZLE_RULES.step),
+            trailhead: trailhead.unwrap_or(PUZZLE_RULES.trailhead),
+            peak: peak.unwrap_or(PUZZLE_RULES.peak),
+        });
+
     let load_start = Instant::now();
 
     let (map, size) = load();
@@ -83,14 +399,21 @@ fn main() {
     println!("   Time: {:?}", load_elapsed);
     println!();
 
+    let sweep_start = Instant::now();
+
+    let puzzle_sweep = sweep(&map, size, PUZZLE_RULES);
+    let reach = &puzzle_sweep.reach;
+
+    let sweep_elapsed = sweep_start.elapsed();
+
+    println!("  Sweep:");
+    println!("   Time: {:?}", sweep_elapsed);
+    println!();
+
     let part_1_start = Instant::now();
 
-    let part_1_solution: usize = (0..size)
-        .flat_map(|y| {
-            (0..size)
-                .map(move |x| (x, y))
-                .map(|(x, y)| score(&map, x, y, size, false))
-        })
+    let part_1_solution: usize = trailheads(&map, PUZZLE_RULES.trailhead)
+        .map(|(x, y)| reach[y][x].peaks.len())
         .sum();
 
     let part_1_elapsed = part_1_start.elapsed();
@@ -101,12 +424,8 @@ fn main() {
 
     let part_2_start = Instant::now();
 
-    let part_2_solution: usize = (0..size)
-        .flat_map(|y| {
-            (0..size)
-                .map(move |x| (x, y))
-                .map(|(x, y)| score(&map, x, y, size, true))
-        })
+    let part_2_solution: usize = trailheads(&map, PUZZLE_RULES.trailhead)
+        .map(|(x, y)| reach[y][x].rating)
         .sum();
 
     let part_2_elapsed = part_2_start.elapsed();
@@ -118,7 +437,201 @@ fn main() {
     println!("  Total:");
     println!(
         "   Time: {:?}",
-        (load_elapsed) + (part_1_elapsed) + (part_2_elapsed)
+        (load_elapsed) + (sweep_elapsed) + (part_1_elapsed) + (part_2_elapsed)
     );
     println!();
+
+    let custom_sweep = custom_rules.map(|rules| {
+        let custom_start = Instant::now();
+
+        let custom_sweep = sweep(&map, size, rules);
+
+        let (score, rating) = trailheads(&map, rules.trailhead)
+            .map(|(x, y)| &custom_sweep.reach[y][x])
+            .fold((0, 0), |(score, rating), reach| {
+                (score + reach.peaks.len(), rating + reach.rating)
+            });
+
+        let custom_elapsed = custom_start.elapsed();
+
+        println!(" Custom: score {score}, rating {rating}");
+        println!("   Time: {:?}", custom_elapsed);
+        println!();
+
+        custom_sweep
+    });
+
+    // the report and the rendering follow the custom rules when there are any
+    let sweep = custom_sweep.as_ref().unwrap_or(&puzzle_sweep);
+
+    if show_report {
+        println!(" Report:");
+
+        for trailhead in report(&map, size, sweep, trail_limit) {
+            let (x, y) = trailhead.position;
+
+            println!(
+                "   ({x}, {y}): score {}, rating {}, peaks {:?}",
+                trailhead.score, trailhead.rating, trailhead.peaks
+            );
+
+            for trail in &trailhead.trails {
+                println!("     {trail:?}");
+            }
+
+            if trailhead.trails.len() < trailhead.rating && trail_limit > 0 {
+                println!(
+                    "     ... {} more",
+                    trailhead.rating - trailhead.trails.len()
+                );
+            }
+        }
+
+        println!();
+    }
+
+    if let Some((x, y)) = render_position {
+        if map.get(y).and_then(|row| row.get(x)) != Some(&Some(sweep.rules.trailhead)) {
+            eprintln!("error: ({x}, {y}) is not a trailhead");
+            process::exit(1);
+        }
+
+        // without a limit every trail is drawn, there are at most `rating` of them
+        let limit = if trail_limit == 0 {
+            sweep.reach[y][x].rating
+        } else {
+            trail_limit
+        };
+
+        println!(" Trails: ({x}, {y})");
+        print!(
+            "{}",
+            render(&map, &trails(&map, size, sweep, (x, y), limit))
+        );
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Sums the scores and ratings of all trailheads.
+    fn totals(input: &str, rules: TrailRules) -> (usize, usize) {
+        let (map, size) = parse(input);
+        let sweep = sweep(&map, size, rules);
+
+        trailheads(&map, rules.trailhead)
+            .map(|(x, y)| &sweep.reach[y][x])
+            .fold((0, 0), |(score, rating), reach| {
+                (score + reach.peaks.len(), rating + reach.rating)
+            })
+    }
+
+    /// The depth first search the sweep replaced, it walks every trail of a trailhead.
+    fn score(map: &[Vec<Tile>], x: usize, y: usize, size: usize, allow_revisit: bool) -> usize {
+        if map[y][x] != Some(0) {
+            return 0;
+        }
+
+        let mut answer = 0;
+
+        let mut stack = vec![(x, y)];
+        let mut visited = HashSet::new();
+
+        while let Some((cur_x, cur_y)) = stack.pop() {
+            let cur_elev = map[cur_y][cur_x].unwrap();
+
+            if !allow_revisit && visited.contains(&(cur_x, cur_y)) {
+                continue;
+            }
+
+            visited.insert((cur_x, cur_y));
+
+            if cur_elev == 9 {
+                answer += 1;
+                continue;
+            }
+
+            for (next_x, next_y) in neighbours(cur_x, cur_y, size) {
+                if map[next_y][next_x] != Some(cur_elev + 1) {
+                    continue;
+                }
+
+                stack.push((next_x, next_y));
+            }
+        }
+
+        answer
+    }
+
+    fn assert_sweep_matches_search(input: &str) {
+        let (map, size) = parse(input);
+        let sweep = sweep(&map, size, PUZZLE_RULES);
+
+        for (x, y) in trailheads(&map, PUZZLE_RULES.trailhead) {
+            let reach = &sweep.reach[y][x];
+
+            assert_eq!(
+                reach.peaks.len(),
+                score(&map, x, y, size, false),
+                "score of ({x}, {y})"
+            );
+            assert_eq!(
+                reach.rating,
+                score(&map, x, y, size, true),
+                "rating of ({x}, {y})"
+            );
+        }
+    }
+
+    #[test]
+    fn example() {
+        assert_eq!(totals(include_str!("example.txt"), PUZZLE_RULES), (36, 81));
+        assert_eq!(totals("0123\n1234\n8765\n9876", PUZZLE_RULES).0, 1);
+    }
+
+    #[test]
+    fn sweep_matches_search_on_example() {
+        assert_sweep_matches_search(include_str!("example.txt"));
+        assert_sweep_matches_search("0123\n1234\n8765\n9876");
+    }
+
+    #[test]
+    fn sweep_matches_search_on_input() {
+        assert_sweep_matches_search(include_str!("input.txt"));
+    }
+
+    #[test]
+    fn impassable_tiles() {
+        let one_trailhead = "..90..9\n...1.98\n...2..7\n6543456\n765.987\n876....\n987....";
+        let two_trailheads = "10..9..\n2...8..\n3...7..\n4567654\n...8..3\n...9..2\n.....01";
+        let three_trails = ".....0.\n..4321.\n..5..2.\n..6543.\n..7..4.\n..8765.\n..9....";
+
+        assert_eq!(totals(one_trailhead, PUZZLE_RULES).0, 4);
+        assert_eq!(totals(two_trailheads, PUZZLE_RULES).0, 3);
+        assert_eq!(totals(three_trails, PUZZLE_RULES).1, 3);
+    }
+
+    #[test]
+    fn step_rules_never_walk_in_circles() {
+        assert!("0..1".parse::<StepRule>().is_err());
+        assert!("-1..1".parse::<StepRule>().is_err());
+        assert!("2..1".parse::<StepRule>().is_err());
+        assert!("1..2".parse::<StepRule>().is_ok());
+        assert!("-2..-1".parse::<StepRule>().is_ok());
+    }
+
+    #[test]
+    fn descending_trails() {
+        let rules = TrailRules {
+            step: "-1".parse().unwrap(),
+            trailhead: 9,
+            peak: 0,
+        };
+
+        // every trail of the puzzle walked backwards, so the totals stay the same
+        assert_eq!(totals(include_str!("example.txt"), rules), (36, 81));
+    }
 }
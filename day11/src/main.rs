@@ -1,44 +1,322 @@
-use cached::proc_macro::cached;
-use std::time::Instant;
+use std::{collections::HashMap, env, fmt, fs, process, str::FromStr, time::Instant};
 
-fn load() -> Vec<usize> {
-    let input = include_str!("input.txt");
+/// The integer type of stone numbers and counts, building with the `wide` feature
+/// switches to `u128` to blink well past the point where 64 bits overflow. Counts
+/// still grow exponentially, so on the puzzle input 64 bits last for 101 blinks and
+/// 128 bits for 208, there is no wider mode.
+#[cfg(not(feature = "wide"))]
+type Value = u64;
 
-    input
-        .trim()
-        .split(" ")
-        .map(|part| part.parse().expect("failed to parse number"))
-        .collect()
+#[cfg(feature = "wide")]
+type Value = u128;
+
+/// How many stones carry each number, the order of the stones never matters.
+type Stones = HashMap<Value, Value>;
+
+#[derive(Debug, PartialEq, Eq)]
+enum OverflowCause {
+    /// The number written on this stone cannot be transformed.
+    Stone(Value),
+    /// There are too many stones carrying this number.
+    Count(Value),
+    /// There are too many stones in total.
+    Total,
+}
+
+/// The first blink whose result does not fit into a `Value`.
+#[derive(Debug, PartialEq, Eq)]
+struct Overflow {
+    blink: usize,
+    cause: OverflowCause,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "blink {} overflows, ", self.blink)?;
+
+        match self.cause {
+            OverflowCause::Stone(stone) => write!(f, "stone {stone} grows too large"),
+            OverflowCause::Count(stone) => write!(f, "too many stones carry {stone}"),
+            OverflowCause::Total => write!(f, "there are too many stones in total"),
+        }?;
+
+        if cfg!(feature = "wide") {
+            write!(f, ", 128 bits is the widest supported mode")?;
+        } else {
+            write!(f, ", try building with `--features wide`")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn load() -> Stones {
+    parse(include_str!("input.txt"))
+}
+
+fn parse(input: &str) -> Stones {
+    let mut stones = Stones::new();
+
+    for part in input.trim().split(" ") {
+        *stones
+            .entry(part.parse().expect("failed to parse number"))
+            .or_default() += 1;
+    }
+
+    stones
+}
+
+/// Which stones a rule applies to.
+#[derive(Clone, Copy)]
+enum Predicate {
+    Is(Value),
+    /// The number has an even count of digits in the given base.
+    EvenDigits(u32),
+    DivisibleBy(Value),
+    Any,
+}
+
+impl Predicate {
+    fn matches(&self, stone: Value) -> bool {
+        match *self {
+            Predicate::Is(value) => stone == value,
+            Predicate::EvenDigits(base) => digits(stone, base).is_multiple_of(2),
+            Predicate::DivisibleBy(divisor) => stone.is_multiple_of(divisor),
+            Predicate::Any => true,
+        }
+    }
+}
+
+/// What a rule turns a stone into.
+#[derive(Clone, Copy)]
+enum Transform {
+    Replace(Value),
+    /// Splits the digits in the given base into a left and a right half, the right
+    /// half gets the extra digit of an odd count.
+    Split(u32),
+    Multiply(Value),
+    Add(Value),
+}
+
+impl Transform {
+    /// Returns the one or two stones a single stone turns into, or `None` if the new
+    /// number does not fit.
+    fn apply(&self, stone: Value) -> Option<(Value, Option<Value>)> {
+        match *self {
+            Transform::Replace(value) => Some((value, None)),
+            Transform::Split(base) => {
+                let divisor = (base as Value).pow(digits(stone, base).div_ceil(2));
+
+                Some((stone / divisor, Some(stone % divisor)))
+            }
+            Transform::Multiply(factor) => Some((stone.checked_mul(factor)?, None)),
+            Transform::Add(value) => Some((stone.checked_add(value)?, None)),
+        }
+    }
+}
+
+fn digits(stone: Value, base: u32) -> u32 {
+    stone.checked_ilog(base as Value).unwrap_or(0) + 1
 }
 
-#[cached]
-fn simulate_stone(stone: usize, iterations: usize) -> usize {
-    if iterations == 0 {
-        return 1;
+#[derive(Clone, Copy)]
+struct Rule {
+    predicate: Predicate,
+    transform: Transform,
+}
+
+/// Parses a rule like `even-digits 10 -> split 10`, the predicate is one of `is <n>`,
+/// `even-digits [base]`, `divisible-by <n>` and `any`, the transform one of
+/// `replace <n>`, `split [base]`, `multiply <n>` and `add <n>`. Bases default to 10.
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (predicate, transform) = s
+            .split_once("->")
+            .ok_or_else(|| format!("invalid rule {s:?}, expected `<predicate> -> <transform>`"))?;
+
+        let predicate = match parse_term(predicate)? {
+            ("is", Some(value)) => Predicate::Is(value),
+            ("even-digits", base) => Predicate::EvenDigits(parse_base(base)?),
+            ("divisible-by", Some(divisor)) if divisor > 0 => Predicate::DivisibleBy(divisor),
+            ("any", None) => Predicate::Any,
+            _ => return Err(format!("invalid predicate {:?}", predicate.trim())),
+        };
+
+        let transform = match parse_term(transform)? {
+            ("replace", Some(value)) => Transform::Replace(value),
+            ("split", base) => Transform::Split(parse_base(base)?),
+            ("multiply", Some(factor)) => Transform::Multiply(factor),
+            ("add", Some(value)) => Transform::Add(value),
+            _ => return Err(format!("invalid transform {:?}", transform.trim())),
+        };
+
+        Ok(Rule {
+            predicate,
+            transform,
+        })
     }
+}
+
+/// Splits a term like `multiply 2024` into its name and optional number.
+fn parse_term(term: &str) -> Result<(&str, Option<Value>), String> {
+    let mut parts = term.split_whitespace();
+
+    let name = parts.next().ok_or("missing term in rule")?;
+    let value = parts
+        .next()
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("invalid number {value:?}"))
+        })
+        .transpose()?;
 
-    if stone == 0 {
-        return simulate_stone(1, iterations - 1);
+    if parts.next().is_some() {
+        return Err(format!("too many arguments in {:?}", term.trim()));
     }
 
-    let digits = stone.checked_ilog10().unwrap_or(0) + 1;
+    Ok((name, value))
+}
 
-    if digits & 1 == 0 {
-        let divisor = 10usize.pow(digits / 2);
+fn parse_base(base: Option<Value>) -> Result<u32, String> {
+    match base.unwrap_or(10) {
+        base @ 2..=36 => Ok(base as u32),
+        base => Err(format!(
+            "invalid base {base}, it has to be between 2 and 36"
+        )),
+    }
+}
 
-        let left = stone / divisor;
-        let right = stone % divisor;
+/// An ordered list of rules, a stone is changed by the first rule that matches it and
+/// kept as it is if none do.
+struct RuleSet(Vec<Rule>);
 
-        return simulate_stone(left, iterations - 1) + simulate_stone(right, iterations - 1);
+impl RuleSet {
+    /// Parses one rule per line, empty lines and lines starting with `#` are skipped.
+    fn parse(text: &str) -> Result<RuleSet, String> {
+        text.lines()
+            .enumerate()
+            .map(|(index, line)| (index, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(index, line)| {
+                line.parse()
+                    .map_err(|err| format!("line {}: {err}", index + 1))
+            })
+            .collect::<Result<_, _>>()
+            .map(RuleSet)
     }
 
-    simulate_stone(stone * 2024, iterations - 1)
+    fn blink_stone(&self, stone: Value) -> Option<(Value, Option<Value>)> {
+        self.0
+            .iter()
+            .find(|rule| rule.predicate.matches(stone))
+            .map_or(Some((stone, None)), |rule| rule.transform.apply(stone))
+    }
+}
+
+fn expect_rules(rules: Result<RuleSet, String>) -> RuleSet {
+    rules.unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        process::exit(1);
+    })
+}
+
+fn add_stones(stones: &mut Stones, stone: Value, count: Value) -> Result<(), OverflowCause> {
+    let total = stones.entry(stone).or_default();
+    *total = total
+        .checked_add(count)
+        .ok_or(OverflowCause::Count(stone))?;
+
+    Ok(())
+}
+
+/// Applies one blink to every stone at once, stones with the same number are only
+/// looked at once whatever the rules are.
+fn blink(rules: &RuleSet, stones: &Stones) -> Result<Stones, OverflowCause> {
+    let mut next = Stones::with_capacity(stones.len());
+
+    for (&stone, &count) in stones {
+        let (left, right) = rules
+            .blink_stone(stone)
+            .ok_or(OverflowCause::Stone(stone))?;
+
+        add_stones(&mut next, left, count)?;
+
+        if let Some(right) = right {
+            add_stones(&mut next, right, count)?;
+        }
+    }
+
+    Ok(next)
+}
+
+/// Blinks `blinks` times, calling `on_blink` with the blink number and the stones after it.
+/// The total is checked after every blink, so `count` never fails on the result.
+fn simulate(
+    rules: &RuleSet,
+    stones: &Stones,
+    blinks: usize,
+    mut on_blink: impl FnMut(usize, &Stones),
+) -> Result<Stones, Overflow> {
+    let mut stones = stones.clone();
+
+    for step in 1..=blinks {
+        let overflow = |cause| Overflow { blink: step, cause };
+
+        stones = blink(rules, &stones).map_err(overflow)?;
+        count(&stones).ok_or_else(|| overflow(OverflowCause::Total))?;
+
+        on_blink(step, &stones);
+    }
+
+    Ok(stones)
+}
+
+fn count(stones: &Stones) -> Option<Value> {
+    stones
+        .values()
+        .try_fold(0 as Value, |total, &count| total.checked_add(count))
+}
+
+fn expect_stones(stones: Result<Stones, Overflow>) -> Stones {
+    stones.unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        process::exit(1);
+    })
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let show_distinct = args.iter().any(|arg| arg == "--distinct");
+
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|index| args.get(index + 1))
+    };
+
+    let custom_blinks: Option<usize> = option("--blinks").map(|blinks| {
+        blinks.parse().unwrap_or_else(|_| {
+            eprintln!("error: invalid blink count {blinks:?}");
+            process::exit(1);
+        })
+    });
+    let custom_rules = option("--rules").map(|path| {
+        let text = fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("error: failed to read rules from {path:?}: {err}");
+            process::exit(1);
+        });
+
+        expect_rules(RuleSet::parse(&text))
+    });
+
     let load_start = Instant::now();
 
     let stones = load();
+    let puzzle_rules = expect_rules(RuleSet::parse(include_str!("rules.txt")));
 
     let load_elapsed = load_start.elapsed();
 
@@ -46,13 +324,14 @@ fn main() {
     println!("   Time: {:?}", load_elapsed);
     println!();
 
+    // the number of distinct stones after every blink
+    let mut distinct = Vec::new();
+    let mut record = |blink: usize, stones: &Stones| distinct.push((blink, stones.len()));
+
     let part_1_start = Instant::now();
 
-    let part_1_solution: usize = stones
-        .iter()
-        .copied()
-        .map(|stone| simulate_stone(stone, 25))
-        .sum();
+    let part_1_stones = expect_stones(simulate(&puzzle_rules, &stones, 25, &mut record));
+    let part_1_solution = count(&part_1_stones).unwrap();
 
     let part_1_elapsed = part_1_start.elapsed();
 
@@ -62,11 +341,14 @@ fn main() {
 
     let part_2_start = Instant::now();
 
-    let part_2_solution: usize = stones
-        .iter()
-        .copied()
-        .map(|stone| simulate_stone(stone, 75))
-        .sum();
+    // part 2 picks up where part 1 stopped
+    let part_2_stones = expect_stones(simulate(
+        &puzzle_rules,
+        &part_1_stones,
+        50,
+        |step, stones| record(step + 25, stones),
+    ));
+    let part_2_solution = count(&part_2_stones).unwrap();
 
     let part_2_elapsed = part_2_start.elapsed();
 
@@ -80,4 +362,106 @@ fn main() {
         (load_elapsed) + (part_1_elapsed) + (part_2_elapsed)
     );
     println!();
+
+    if custom_blinks.is_some() || custom_rules.is_some() {
+        let blinks = custom_blinks.unwrap_or(75);
+        let rules = custom_rules.as_ref().unwrap_or(&puzzle_rules);
+
+        let custom_start = Instant::now();
+
+        distinct.clear();
+        let custom_stones = expect_stones(simulate(rules, &stones, blinks, |blink, stones| {
+            distinct.push((blink, stones.len()))
+        }));
+
+        let custom_elapsed = custom_start.elapsed();
+
+        println!(
+            " Custom: {} ({blinks} blinks)",
+            count(&custom_stones).unwrap()
+        );
+        println!("   Time: {:?}", custom_elapsed);
+        println!();
+    }
+
+    if show_distinct {
+        println!("Distinct stones:");
+
+        for (blink, distinct) in distinct {
+            println!("  {:>4}: {}", blink, distinct);
+        }
+
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn puzzle_rules() -> RuleSet {
+        RuleSet::parse(include_str!("rules.txt")).unwrap()
+    }
+
+    #[cfg(not(feature = "wide"))]
+    #[test]
+    fn reports_the_blink_that_overflows() {
+        assert_eq!(
+            simulate(&puzzle_rules(), &load(), 1000, |_, _| ()).unwrap_err(),
+            Overflow {
+                blink: 102,
+                cause: OverflowCause::Total,
+            }
+        );
+    }
+
+    #[cfg(feature = "wide")]
+    #[test]
+    fn reports_the_blink_that_overflows() {
+        assert_eq!(
+            simulate(&puzzle_rules(), &load(), 1000, |_, _| ()).unwrap_err(),
+            Overflow {
+                blink: 209,
+                cause: OverflowCause::Total,
+            }
+        );
+    }
+
+    fn rule(text: &str) -> Result<Rule, String> {
+        text.parse()
+    }
+
+    #[test]
+    fn puzzle_rules_solve_the_example() {
+        let stones = simulate(&puzzle_rules(), &parse("125 17"), 25, |_, _| ()).unwrap();
+
+        assert_eq!(count(&stones), Some(55312));
+    }
+
+    #[test]
+    fn splits_put_the_extra_digit_on_the_right() {
+        let binary = rule("any -> split 2").unwrap();
+        let decimal = rule("any -> split").unwrap();
+
+        assert_eq!(binary.transform.apply(0b1011), Some((0b10, Some(0b11))));
+        assert_eq!(binary.transform.apply(0b101), Some((0b1, Some(0b01))));
+        assert_eq!(decimal.transform.apply(12345), Some((12, Some(345))));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        assert!(rule("divisible-by 0 -> add 1").is_err());
+        assert!(rule("any 3 -> add 1").is_err());
+        assert!(rule("any -> split 1").is_err());
+        assert!(rule("any -> multiply").is_err());
+        assert!(RuleSet::parse("is 0 -> replace 1\nany").is_err());
+    }
+
+    #[test]
+    fn unmatched_stones_are_kept() {
+        let rules = RuleSet::parse("is 0 -> replace 1").unwrap();
+
+        assert_eq!(rules.blink_stone(0), Some((1, None)));
+        assert_eq!(rules.blink_stone(7), Some((7, None)));
+    }
 }